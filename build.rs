@@ -4,4 +4,25 @@ fn main() {
         use napi_build::setup;
         setup();
     }
+
+    // Expose the nanvix revision actually built, as locked in Cargo.lock, to
+    // `Runtime::diagnose` and the CLI
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lockfile = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let revision = lockfile
+        .split("[[package]]")
+        .find(|package| {
+            package
+                .lines()
+                .any(|line| line.trim() == "name = \"nanvix\"")
+        })
+        .and_then(|package| {
+            package
+                .lines()
+                .find_map(|line| line.trim().strip_prefix("source = \"git+"))
+        })
+        .and_then(|source| source.split('#').nth(1))
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or("unknown");
+    println!("cargo:rustc-env=NANVIX_REVISION={}", revision);
 }
@@ -43,7 +43,7 @@ async fn main() -> Result<()> {
     let mut sandbox = Sandbox::new(config)?;
 
     match sandbox.run("guest-examples/file_ops.js").await {
-        Ok(()) => {
+        Ok(_) => {
             println!("Workload completed successfully with syscall interception!");
         }
         Err(e) => {
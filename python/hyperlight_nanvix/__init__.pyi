@@ -1,9 +1,17 @@
-from typing import Optional
+from typing import Callable, Optional
+
+ProgressCallback = Callable[[str, int, Optional[int]], None]
 
 class SandboxConfig:
     log_directory: Optional[str]
     tmp_directory: Optional[str]
-    def __init__(self, log_directory: Optional[str] = None, tmp_directory: Optional[str] = None) -> None: ...
+    on_progress: Optional[ProgressCallback]
+    def __init__(
+        self,
+        log_directory: Optional[str] = None,
+        tmp_directory: Optional[str] = None,
+        on_progress: Optional[ProgressCallback] = None,
+    ) -> None: ...
 
 class WorkloadResult:
     success: bool
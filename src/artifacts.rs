@@ -0,0 +1,335 @@
+//! Collection of guest-written files as run artifacts.
+//!
+//! Files the guest writes below a declared output directory are redirected into
+//! a per-run staging directory on the host. After the run they are returned in
+//! the run result, or copied to a host destination, and the staging directory
+//! is removed. The staging directory is also removed when the collector is
+//! dropped without collecting, e.g. because the run failed.
+
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use nanvix::log;
+
+use crate::middleware::{resolve_path, Next, Syscall, SyscallMiddleware, SyscallResult};
+
+/// Default limit on the size of a single artifact
+const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Default limit on the combined size of all artifacts of a run
+const DEFAULT_MAX_TOTAL_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Configuration of artifact collection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactsConfig {
+    /// Guest directory whose contents are collected
+    pub guest_directory: String,
+    /// Host directory artifacts are copied to instead of being returned in the result
+    pub destination: Option<String>,
+    /// Largest file the guest may write, in bytes
+    pub max_file_size: u64,
+    /// Largest amount of data the guest may write in total, in bytes
+    pub max_total_size: u64,
+}
+
+impl ArtifactsConfig {
+    pub fn new<S: Into<String>>(guest_directory: S) -> Self {
+        Self {
+            guest_directory: guest_directory.into().trim_end_matches('/').to_string(),
+            destination: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+        }
+    }
+
+    pub fn with_destination<S: Into<String>>(mut self, dir: S) -> Self {
+        self.destination = Some(dir.into());
+        self
+    }
+
+    pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    pub fn with_max_total_size(mut self, bytes: u64) -> Self {
+        self.max_total_size = bytes;
+        self
+    }
+}
+
+/// A file written by the guest below the artifacts directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    /// Path relative to the artifacts directory
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    /// File contents, when no host destination is configured
+    pub contents: Option<Vec<u8>>,
+    /// Location of the copy, when a host destination is configured
+    pub host_path: Option<String>,
+}
+
+/// Middleware redirecting guest writes below the artifacts directory into staging
+pub struct ArtifactCollector {
+    config: ArtifactsConfig,
+    staging: PathBuf,
+    state: Mutex<CollectorState>,
+}
+
+#[derive(Default)]
+struct CollectorState {
+    /// Guest file descriptors open on staged files
+    fds: HashSet<i32>,
+    /// Bytes written to staged files so far
+    written: u64,
+}
+
+impl ArtifactCollector {
+    /// Create a collector staging files in `staging`
+    pub fn new<P: Into<PathBuf>>(config: ArtifactsConfig, staging: P) -> Result<Self> {
+        let staging = staging.into();
+        std::fs::create_dir_all(&staging).with_context(|| {
+            format!("Failed to create artifact staging directory {:?}", staging)
+        })?;
+        Ok(Self {
+            config,
+            staging,
+            state: Mutex::new(CollectorState::default()),
+        })
+    }
+
+    /// Host path a guest path is redirected to, if it is below the artifacts directory.
+    ///
+    /// The guest path is resolved against the working directory and
+    /// normalized first, so neither relative paths nor `..` components can
+    /// reach the artifacts directory from outside or leave the staging
+    /// directory from inside.
+    fn staged_path(&self, dirfd: i32, guest_path: &CStr) -> Option<CString> {
+        let guest_path = resolve_path(dirfd, guest_path.to_str().ok()?)?;
+        let relative = guest_path.strip_prefix(&self.config.guest_directory)?;
+        if !relative.is_empty() && !relative.starts_with('/') {
+            return None;
+        }
+        let staged = format!("{}{}", self.staging.to_string_lossy(), relative);
+        CString::new(staged).ok()
+    }
+
+    /// Gather the staged files and remove the staging directory
+    pub fn collect(&self) -> Result<Vec<Artifact>> {
+        let mut files = Vec::new();
+        collect_files(&self.staging, &mut files)?;
+        files.sort();
+
+        let mut artifacts = Vec::new();
+        let mut total = 0;
+        for file in files {
+            let relative = file
+                .strip_prefix(&self.staging)?
+                .to_string_lossy()
+                .to_string();
+            let size = std::fs::metadata(&file)?.len();
+            total += size;
+            if size > self.config.max_file_size || total > self.config.max_total_size {
+                anyhow::bail!(
+                    "Artifact {} exceeds the artifact size limits ({} bytes per file, {} bytes in total)",
+                    relative,
+                    self.config.max_file_size,
+                    self.config.max_total_size
+                );
+            }
+
+            let artifact = match &self.config.destination {
+                Some(destination) => {
+                    let host_path = Path::new(destination).join(&relative);
+                    if let Some(parent) = host_path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::copy(&file, &host_path).with_context(|| {
+                        format!("Failed to copy artifact {} to {:?}", relative, host_path)
+                    })?;
+                    Artifact {
+                        path: relative,
+                        size,
+                        contents: None,
+                        host_path: Some(host_path.to_string_lossy().to_string()),
+                    }
+                }
+                None => Artifact {
+                    path: relative,
+                    size,
+                    contents: Some(std::fs::read(&file)?),
+                    host_path: None,
+                },
+            };
+            artifacts.push(artifact);
+        }
+
+        std::fs::remove_dir_all(&self.staging)?;
+        Ok(artifacts)
+    }
+}
+
+impl Drop for ArtifactCollector {
+    fn drop(&mut self) {
+        if self.staging.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&self.staging) {
+                log::warn!(
+                    "Failed to remove artifact staging directory {}: {}",
+                    self.staging.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl SyscallMiddleware for ArtifactCollector {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        match call {
+            Syscall::Openat { dirfd, path, .. } => match self.staged_path(*dirfd, path) {
+                Some(staged) => {
+                    *path = staged;
+                    let fd = next.run(call)?;
+                    if let Ok(mut state) = self.state.lock() {
+                        state.fds.insert(fd as i32);
+                    }
+                    Ok(fd)
+                }
+                None => next.run(call),
+            },
+            Syscall::Unlink { path } | Syscall::Mkdir { path, .. } => {
+                if let Some(staged) = self.staged_path(libc::AT_FDCWD, path) {
+                    *path = staged;
+                }
+                next.run(call)
+            }
+            Syscall::Write { fd, buf } => {
+                let fd = *fd;
+                let len = buf.len() as u64;
+                let mut state = self.state.lock().map_err(|_| libc::EIO)?;
+                if !state.fds.contains(&fd) {
+                    drop(state);
+                    return next.run(call);
+                }
+                if state.written + len > self.config.max_total_size {
+                    return Err(libc::ENOSPC);
+                }
+                let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+                if unsafe { libc::fstat(fd, &mut stat) } == 0
+                    && stat.st_size as u64 + len > self.config.max_file_size
+                {
+                    return Err(libc::EFBIG);
+                }
+                let written = next.run(call)?;
+                state.written += written as u64;
+                Ok(written)
+            }
+            Syscall::Close { fd } => {
+                if let Ok(mut state) = self.state.lock() {
+                    state.fds.remove(fd);
+                }
+                next.run(call)
+            }
+            _ => next.run(call),
+        }
+    }
+}
+
+/// Recursively list the regular files below `dir`
+pub(crate) fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{relative_to_cwd, write_through_chain, TempDir};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_artifacts_are_collected() {
+        let root = TempDir::new("artifacts");
+        let staging = root.join("staging");
+        let collector = Arc::new(
+            ArtifactCollector::new(
+                ArtifactsConfig::new("/guest/out/").with_max_file_size(8),
+                &staging,
+            )
+            .unwrap(),
+        );
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![collector.clone()];
+
+        assert_eq!(
+            write_through_chain(&layers, "/guest/out/report.csv", b"a,b\n"),
+            Ok(4)
+        );
+        assert_eq!(
+            write_through_chain(&layers, "/guest/out/too-big.bin", b"0123456789"),
+            Err(libc::EFBIG)
+        );
+        // Paths outside the artifacts directory are not redirected
+        assert!(write_through_chain(&layers, "/guest/output/x", b"x").is_err());
+        // `..` is folded before matching, so it cannot leave the staging directory
+        assert!(write_through_chain(&layers, "/guest/out/../../../escape", b"x").is_err());
+        assert_eq!(
+            write_through_chain(&layers, "/guest/tmp/../out/./plot.png", b"png"),
+            Ok(3)
+        );
+
+        let artifacts = collector.collect().unwrap();
+        assert_eq!(artifacts.len(), 3);
+        assert_eq!(artifacts[0].path, "plot.png");
+        assert_eq!(artifacts[1].path, "report.csv");
+        assert_eq!(artifacts[1].contents.as_deref(), Some(&b"a,b\n"[..]));
+        assert_eq!(artifacts[2].path, "too-big.bin");
+        assert_eq!(artifacts[2].size, 0);
+        assert!(!staging.exists());
+    }
+
+    #[test]
+    fn test_relative_artifact_paths_are_collected() {
+        let root = TempDir::new("artifacts-relative");
+        let out = root.join("out");
+        let collector = Arc::new(
+            ArtifactCollector::new(
+                ArtifactsConfig::new(out.to_string_lossy()),
+                root.join("staging"),
+            )
+            .unwrap(),
+        );
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![collector.clone()];
+
+        let relative = relative_to_cwd(&out.join("relative.txt"));
+        assert_eq!(write_through_chain(&layers, &relative, b"rel"), Ok(3));
+        assert!(!out.exists());
+
+        let artifacts = collector.collect().unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].path, "relative.txt");
+    }
+
+    #[test]
+    fn test_artifact_staging_removed_without_collect() {
+        let root = TempDir::new("artifacts-dropped");
+        let staging = root.join("staging");
+        let collector =
+            ArtifactCollector::new(ArtifactsConfig::new("/guest/out"), &staging).unwrap();
+        assert!(staging.exists());
+        drop(collector);
+        assert!(!staging.exists());
+    }
+}
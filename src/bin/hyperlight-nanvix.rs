@@ -1,58 +1,54 @@
 use anyhow::Result;
-use hyperlight_nanvix::{cache, RuntimeConfig, Sandbox};
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, State};
+use axum::http::StatusCode;
+use axum::Json;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use hyperlight_nanvix::{
+    bundle, cache, ArtifactSource, BinaryVersion, CheckStatus, DownloadProgress, LineReader,
+    MirrorSource, ProgressCallback, RegistrySource, RunFailure, RunResult, RuntimeConfig, Sandbox,
+    Verification, WorkloadType,
+};
 use nanvix::log;
-use nanvix::registry::Registry;
-use std::env;
-use std::path::Path;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Default log-level (overridden by RUST_LOG environment variable if set).
 const DEFAULT_LOG_LEVEL: &str = "info";
 
-async fn setup_registry_command() -> Result<()> {
+async fn setup_registry_command(
+    cache_dir: &Path,
+    source: &dyn ArtifactSource,
+    binaries: &[String],
+) -> Result<()> {
     println!("Setting up Nanvix registry...");
 
     // Check cache status first using shared cache utilities
-    let kernel_cached = cache::is_binary_cached("kernel.elf");
-    let qjs_cached = cache::is_binary_cached("qjs");
-    let python_cached = cache::is_binary_cached("python3");
-
-    if kernel_cached && qjs_cached && python_cached {
-        println!("Registry already set up at ~/.cache/nanvix-registry/");
+    if binaries
+        .iter()
+        .all(|binary| cache::is_binary_cached(cache_dir, binary))
+    {
+        println!("Registry already set up at {}", cache_dir.display());
     } else {
-        // Trigger registry download by requesting key binaries
-        let registry = Registry::new(None);
-
-        if !kernel_cached {
-            print!("Downloading kernel.elf... ");
-            let _kernel = registry
-                .get_cached_binary("hyperlight", "single-process", "kernel.elf")
-                .await?;
-            println!("done");
-        } else {
-            println!("kernel.elf already cached");
-        }
-
-        if !qjs_cached {
-            print!("Downloading qjs binary... ");
-            let _qjs = registry
-                .get_cached_binary("hyperlight", "single-process", "qjs")
-                .await?;
-            println!("done");
-        } else {
-            println!("qjs already cached");
+        // Trigger registry download by requesting the selected binaries
+        for binary in binaries {
+            if cache::is_binary_cached(cache_dir, binary) {
+                println!("{} already cached", binary);
+            } else {
+                println!("Downloading {}...", binary);
+                cache::fetch_binary(cache_dir, source, binary, Some(&progress_bar())).await?;
+            }
         }
 
-        if !python_cached {
-            print!("Downloading python3 binary... ");
-            let _python = registry
-                .get_cached_binary("hyperlight", "single-process", "python3")
-                .await?;
-            println!("done");
-        } else {
-            println!("python3 already cached");
-        }
-
-        println!("\nRegistry setup complete at ~/.cache/nanvix-registry/");
+        println!("\nRegistry setup complete at {}", cache_dir.display());
     }
 
     println!("\nTo compile and run C/C++ programs, see the README:");
@@ -63,96 +59,1484 @@ async fn setup_registry_command() -> Result<()> {
     Ok(())
 }
 
-async fn clear_registry_command() -> Result<()> {
-    println!("Clearing Nanvix registry cache...");
+async fn clear_registry_command(cache_dir: &Path) -> Result<()> {
+    println!(
+        "Clearing Nanvix registry cache at {}...",
+        cache_dir.display()
+    );
 
     // Create a minimal config to instantiate the Sandbox for cache clearing
-    let config = RuntimeConfig::new();
+    let config = RuntimeConfig::new().with_cache_directory(cache_dir.to_string_lossy());
     let sandbox = Sandbox::new(config)?;
 
-    match sandbox.clear_cache().await {
-        Ok(()) => println!("Cache cleared successfully"),
-        Err(e) => {
-            eprintln!("Error clearing cache: {}", e);
-            std::process::exit(1);
+    sandbox
+        .clear_cache()
+        .await
+        .map_err(|e| anyhow::anyhow!("Error clearing cache: {}", e))?;
+    println!("Cache cleared successfully");
+
+    println!("Run 'hyperlight-nanvix registry setup' to re-download if needed.");
+    Ok(())
+}
+
+fn cache_command(command: CacheCommand, cache_dir: &Path) -> Result<()> {
+    match command {
+        CacheCommand::List => {
+            let binaries = cache::list_binaries(cache_dir)?;
+            if binaries.is_empty() {
+                println!("No cached binaries in {}", cache_dir.display());
+                return Ok(());
+            }
+            println!(
+                "{:<12} {:<12} {:>12}  {:<16} LAST USED",
+                "RELEASE", "ARTIFACT", "SIZE", "SHA256"
+            );
+            for binary in binaries {
+                println!(
+                    "{:<12} {:<12} {:>12}  {:<16} {}",
+                    binary.release.as_deref().unwrap_or("-"),
+                    binary.name,
+                    binary.size,
+                    binary
+                        .sha256
+                        .as_deref()
+                        .map_or("unrecorded", |digest| digest.get(..16).unwrap_or(digest)),
+                    binary.last_used.map_or("never".to_string(), format_age)
+                );
+            }
+        }
+        CacheCommand::Verify => {
+            let mut failed = 0;
+            for (binary, integrity) in cache::verify_binaries(cache_dir)? {
+                let release = binary.release.as_deref().unwrap_or("-");
+                match integrity {
+                    cache::Integrity::Valid => println!("ok          {} {}", release, binary.name),
+                    cache::Integrity::Unrecorded => {
+                        println!("unrecorded  {} {}", release, binary.name)
+                    }
+                    cache::Integrity::Mismatch { expected, actual } => {
+                        failed += 1;
+                        println!(
+                            "MISMATCH    {} {} (expected sha256 {}, found {})",
+                            release, binary.name, expected, actual
+                        );
+                    }
+                }
+            }
+            if failed > 0 {
+                anyhow::bail!("{} cached binaries failed verification", failed);
+            }
+        }
+        CacheCommand::Prune { keep } => {
+            let removed = cache::prune_releases(cache_dir, keep)?;
+            if removed.is_empty() {
+                println!("Nothing to prune");
+            }
+            for release in removed {
+                println!("Removed release {}", release);
+            }
         }
     }
+    Ok(())
+}
 
-    println!("Run 'cargo run -- --setup-registry' to re-download if needed.");
+async fn registry_command(
+    command: RegistryCommand,
+    registry_dir: &Path,
+    source: &dyn ArtifactSource,
+) -> Result<()> {
+    match command {
+        RegistryCommand::Setup { only, all } => {
+            let binaries = if all {
+                source.list().await?
+            } else if !only.is_empty() {
+                // Every sandbox boots the kernel
+                let mut binaries = vec![cache::KERNEL_BINARY.to_string()];
+                for binary in only.iter().map(|b| b.trim()).filter(|b| !b.is_empty()) {
+                    if !binaries.iter().any(|selected| selected == binary) {
+                        binaries.push(binary.to_string());
+                    }
+                }
+                binaries
+            } else {
+                cache::KNOWN_BINARIES
+                    .iter()
+                    .map(|binary| binary.to_string())
+                    .collect()
+            };
+            setup_registry_command(registry_dir, source, &binaries).await?;
+        }
+        RegistryCommand::Clear => clear_registry_command(registry_dir).await?,
+        RegistryCommand::List => {
+            let binaries = source.list().await?;
+            println!("Binaries available from {}:", source.describe());
+            for binary in binaries {
+                let status = if cache::is_binary_cached(registry_dir, &binary) {
+                    "cached"
+                } else {
+                    "-"
+                };
+                println!("  {:<16} {}", binary, status);
+            }
+        }
+        RegistryCommand::Export { bundle } => {
+            bundle::export_bundle(registry_dir, &bundle)?;
+            println!(
+                "Exported {} to {}",
+                registry_dir.display(),
+                bundle.display()
+            );
+        }
+        RegistryCommand::Import { bundle } => {
+            bundle::import_bundle(&bundle, registry_dir)?;
+            println!(
+                "Imported {} into {}",
+                bundle.display(),
+                registry_dir.display()
+            );
+        }
+    }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Parse command line arguments first
-    let args: Vec<String> = env::args().collect();
+/// Check that this host can run sandboxes, failing if any check does not pass
+fn doctor_command(global: &GlobalArgs, args: SandboxArgs, defaults: &RuntimeConfig) -> Result<()> {
+    let sandbox = Sandbox::new(sandbox_config(global, args, None, defaults)?)?;
+    let diagnosis = sandbox.diagnose();
+
+    println!(
+        "hyperlight-nanvix {} (nanvix {})",
+        env!("CARGO_PKG_VERSION"),
+        diagnosis.nanvix_revision
+    );
+    println!();
+    for check in &diagnosis.checks {
+        let status = match check.status {
+            CheckStatus::Pass => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+        };
+        println!("{:<9} {:<16} {}", status, check.name, check.detail);
+        if let Some(fix) = &check.fix {
+            println!("{:<9} {:<16} fix: {}", "", "", fix);
+        }
+    }
+
+    println!();
+    if diagnosis.artifacts.is_empty() {
+        println!("No cached artifacts");
+    } else {
+        println!(
+            "{:<12} {:<12} {:>12}  SHA256",
+            "RELEASE", "ARTIFACT", "SIZE"
+        );
+        for binary in &diagnosis.artifacts {
+            println!(
+                "{:<12} {:<12} {:>12}  {}",
+                binary.release.as_deref().unwrap_or("-"),
+                binary.name,
+                binary.size,
+                binary.sha256.as_deref().unwrap_or("unrecorded")
+            );
+        }
+    }
+
+    let failed = diagnosis
+        .checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+    Ok(())
+}
+
+/// Width of the CLI progress bar, in characters
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Progress callback drawing a progress bar on stderr
+fn progress_bar() -> ProgressCallback {
+    Arc::new(|progress: &DownloadProgress| {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        match progress.total {
+            Some(total) if total > 0 => {
+                let percent = (progress.downloaded * 100 / total).min(100) as usize;
+                let filled = percent * PROGRESS_BAR_WIDTH / 100;
+                eprint!(
+                    "\r  {:<20} [{}{}] {:>3}% {:.1}/{:.1} MiB",
+                    progress.artifact,
+                    "#".repeat(filled),
+                    " ".repeat(PROGRESS_BAR_WIDTH - filled),
+                    percent,
+                    mib(progress.downloaded),
+                    mib(total)
+                );
+                if progress.downloaded >= total {
+                    eprintln!();
+                }
+            }
+            _ => eprint!(
+                "\r  {:<20} {:.1} MiB",
+                progress.artifact,
+                mib(progress.downloaded)
+            ),
+        }
+    })
+}
+
+/// Time elapsed since `secs` (seconds since the Unix epoch), e.g. "3h ago"
+fn format_age(secs: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let age = now.saturating_sub(secs);
+    match age {
+        0..60 => format!("{}s ago", age),
+        60..3600 => format!("{}m ago", age / 60),
+        3600..86400 => format!("{}h ago", age / 3600),
+        _ => format!("{}d ago", age / 86400),
+    }
+}
+
+/// Run JavaScript, Python, C and C++ programs in Nanvix sandboxes
+///
+/// Without a subcommand, the arguments are those of `run`, e.g.
+/// `hyperlight-nanvix hello.py -- arg` or `hyperlight-nanvix -e 'console.log(1+1)'`.
+/// Exits with 0 on success, 1 if the command or workload fails and 2 on invalid usage.
+#[derive(Parser)]
+#[command(
+    name = "hyperlight-nanvix",
+    version,
+    arg_required_else_help = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Options shared by every subcommand
+#[derive(Args)]
+struct GlobalArgs {
+    /// Show detailed nanvix logging
+    #[arg(long, global = true)]
+    verbose: bool,
+
+    /// Registry cache directory (default: $HYPERLIGHT_NANVIX_CACHE,
+    /// $XDG_CACHE_HOME/nanvix-registry or ~/.cache/nanvix-registry)
+    #[arg(long, global = true, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Use a pinned Nanvix release, cached in its own directory
+    #[arg(long, global = true, value_name = "TAG")]
+    release: Option<String>,
+
+    /// Fetch binaries from a file:// or HTTP(S) mirror of the registry
+    #[arg(long, global = true, value_name = "URL")]
+    mirror: Option<String>,
+
+    /// Configuration file (default: $HYPERLIGHT_NANVIX_CONFIG, the nearest
+    /// hyperlight-nanvix.toml or ~/.config/hyperlight-nanvix.toml)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
+impl GlobalArgs {
+    /// Command-line flags reproducing these options
+    fn to_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if self.verbose {
+            flags.push("--verbose".to_string());
+        }
+        if let Some(dir) = &self.cache_dir {
+            flags.extend([
+                "--cache-dir".to_string(),
+                dir.to_string_lossy().into_owned(),
+            ]);
+        }
+        if let Some(release) = &self.release {
+            flags.extend(["--release".to_string(), release.clone()]);
+        }
+        if let Some(url) = &self.mirror {
+            flags.extend(["--mirror".to_string(), url.clone()]);
+        }
+        if let Some(path) = &self.config {
+            flags.extend(["--config".to_string(), path.to_string_lossy().into_owned()]);
+        }
+        flags
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a workload (.js, .mjs, .py, .elf, .o or a compiled binary)
+    Run(RunArgs),
+    /// Start an interactive JavaScript or Python interpreter
+    Repl {
+        /// Interpreter to start
+        #[arg(long, value_enum, default_value_t = Lang::Js)]
+        lang: Lang,
+
+        #[command(flatten)]
+        sandbox: SandboxArgs,
+    },
+    /// Set up, clear, list, export or import the Nanvix registry
+    #[command(subcommand)]
+    Registry(RegistryCommand),
+    /// List, verify or prune cached binaries
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Run every workload in a directory or matching a glob, in parallel
+    Batch(BatchArgs),
+    /// Serve an HTTP API running workloads, e.g. `POST /v1/run`
+    Serve(ServeArgs),
+    /// Check that this host can run sandboxes and show the cached artifacts
+    Doctor(SandboxArgs),
+    /// Print version information
+    Version,
+}
+
+/// Language of inline code
+#[derive(Clone, Copy, ValueEnum)]
+enum Lang {
+    #[value(alias = "javascript")]
+    Js,
+    #[value(alias = "py")]
+    Python,
+}
+
+impl From<Lang> for WorkloadType {
+    fn from(lang: Lang) -> Self {
+        match lang {
+            Lang::Js => WorkloadType::JavaScript,
+            Lang::Python => WorkloadType::Python,
+        }
+    }
+}
+
+/// Options of the sandbox a workload or REPL runs in
+#[derive(Args)]
+struct SandboxArgs {
+    /// Run from a local artifact directory without contacting the registry
+    #[arg(long, value_name = "DIR")]
+    offline_dir: Option<String>,
+
+    /// Skip hashing cached binaries whose size and mtime are unchanged
+    #[arg(long)]
+    fast_verify: bool,
+
+    /// Boot this kernel instead of the cached one
+    #[arg(long, value_name = "PATH")]
+    kernel: Option<String>,
+
+    /// Use this interpreter for the script instead of the cached one
+    #[arg(long, value_name = "PATH")]
+    interpreter: Option<String>,
+
+    /// Directory for sockets and temporary files [default: /tmp/hyperlight-nanvix]
+    #[arg(long, value_name = "DIR")]
+    tmp_dir: Option<String>,
+
+    /// Directory for logs, including the guest console log [default: /tmp/hyperlight-nanvix]
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<String>,
+}
+
+impl SandboxArgs {
+    /// Command-line flags reproducing these options, except the directories
+    fn to_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        if let Some(dir) = &self.offline_dir {
+            flags.extend(["--offline-dir".to_string(), dir.clone()]);
+        }
+        if self.fast_verify {
+            flags.push("--fast-verify".to_string());
+        }
+        if let Some(path) = &self.kernel {
+            flags.extend(["--kernel".to_string(), path.clone()]);
+        }
+        if let Some(path) = &self.interpreter {
+            flags.extend(["--interpreter".to_string(), path.clone()]);
+        }
+        flags
+    }
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    sandbox: SandboxArgs,
+
+    /// Evaluate this code instead of running a file
+    #[arg(
+        short = 'e',
+        long = "eval",
+        value_name = "CODE",
+        conflicts_with = "script"
+    )]
+    eval: Option<String>,
+
+    /// Same as --eval, e.g. `-c 'print(2**10)' --lang python`
+    #[arg(short = 'c', long = "code", value_name = "CODE", conflicts_with_all = ["script", "eval"])]
+    code: Option<String>,
+
+    /// Language of --eval, --code or a script read from stdin
+    #[arg(long, value_enum, default_value_t = Lang::Js)]
+    lang: Lang,
+
+    /// Workload to run, or `-` to read a script from stdin
+    #[arg(required_unless_present_any = ["eval", "code"])]
+    script: Option<PathBuf>,
+
+    /// Print the outcome as text, or as one JSON object on stdout
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+
+    /// Arguments passed to the guest program, after `--`
+    #[arg(last = true, value_name = "ARGS")]
+    guest_args: Vec<String>,
+}
+
+#[derive(Args)]
+struct BatchArgs {
+    /// Directory of workloads, or a glob such as 'scripts/**/*.py'
+    #[arg(value_name = "DIR|GLOB")]
+    workloads: String,
+
+    /// Number of workloads run at once (default: number of CPUs)
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Seconds after which a workload is stopped and reported as timed out
+    #[arg(long, value_name = "SECS", default_value_t = 300)]
+    timeout: u64,
+
+    /// Write a JUnit XML report to this file
+    #[arg(long, value_name = "PATH")]
+    junit: Option<PathBuf>,
+
+    /// Write a JSON report to this file
+    #[arg(long, value_name = "PATH")]
+    json: Option<PathBuf>,
+
+    #[command(flatten)]
+    sandbox: SandboxArgs,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: std::net::SocketAddr,
+
+    /// Number of workloads run at once; further requests wait (default: number of CPUs).
+    ///
+    /// Each run gets its own worker process rather than a `Runtime` from a
+    /// pool: runs with syscall middleware are serialized within a process, and
+    /// only a separate process can be killed when it times out.
+    #[arg(long, value_name = "N")]
+    max_concurrency: Option<usize>,
+
+    /// Number of requests waiting for a worker; more are rejected with 503
+    /// (default: four times --max-concurrency)
+    #[arg(long, value_name = "N")]
+    max_queue: Option<usize>,
+
+    /// Default and longest timeout of a run, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    timeout: u64,
+
+    #[command(flatten)]
+    sandbox: SandboxArgs,
+}
+
+/// Format of the outcome of `run`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// The guest's output, and errors on stderr
+    Text,
+    /// Status, captured output, duration, binaries and policy violations
+    Json,
+}
+
+#[derive(Subcommand)]
+enum RegistryCommand {
+    /// Download the kernel and interpreters
+    Setup {
+        /// Set up only these binaries (and the kernel), e.g. python3
+        #[arg(
+            long,
+            value_delimiter = ',',
+            value_name = "BINARIES",
+            conflicts_with = "all"
+        )]
+        only: Vec<String>,
+        /// Set up every binary a mirror publishes (the upstream registry publishes no list)
+        #[arg(long)]
+        all: bool,
+    },
+    /// Clear the registry cache
+    Clear,
+    /// List the binaries a mirror publishes (the upstream registry publishes no list)
+    List,
+    /// Export the cache as a bundle
+    Export {
+        #[arg(value_name = "BUNDLE.tar.zst")]
+        bundle: PathBuf,
+    },
+    /// Import a bundle into the cache
+    Import {
+        #[arg(value_name = "BUNDLE.tar.zst")]
+        bundle: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Show artifacts, releases, sizes, digests and last use
+    List,
+    /// Re-hash every cached binary against the manifest
+    Verify,
+    /// Remove all but the most recently used pinned releases
+    Prune {
+        /// Number of releases to keep
+        #[arg(long, value_name = "N")]
+        keep: usize,
+    },
+}
+
+async fn run_command(global: &GlobalArgs, args: RunArgs, defaults: &RuntimeConfig) -> Result<()> {
+    let output = args.output;
+    let (workload_type, result) = run_workload(global, args, defaults).await;
+    if output == Output::Json {
+        println!("{}", run_report(workload_type, &result));
+    }
+    result.map_err(|e| anyhow::anyhow!("Error running workload: {}", e))?;
+    Ok(())
+}
+
+/// Run the workload given on the command line, returning its type if known
+async fn run_workload(
+    global: &GlobalArgs,
+    args: RunArgs,
+    defaults: &RuntimeConfig,
+) -> (Option<WorkloadType>, Result<RunResult>) {
+    let workload_type = match (args.eval.is_some() || args.code.is_some(), &args.script) {
+        (true, _) => Some(WorkloadType::from(args.lang)),
+        (false, Some(script)) if script.as_os_str() == "-" => Some(WorkloadType::from(args.lang)),
+        (false, Some(script)) => WorkloadType::from_path(script),
+        (false, None) => None,
+    };
+    let result = async {
+        // Inline code from --eval, --code or stdin is staged by the runtime
+        let inline_code = match (args.eval.or(args.code), &args.script) {
+            (Some(code), _) => Some(code),
+            (None, Some(script)) if script.as_os_str() == "-" => {
+                let mut code = String::new();
+                std::io::stdin().read_to_string(&mut code)?;
+                Some(code)
+            }
+            (None, Some(script)) => {
+                // Check if file exists
+                if !script.exists() {
+                    anyhow::bail!("File {:?} does not exist", script);
+                }
+                None
+            }
+            (None, None) => unreachable!("clap requires a script, --eval or --code"),
+        };
+
+        // Create Sandbox instance
+        let config = sandbox_config(global, args.sandbox, workload_type, defaults)?
+            .with_capture_output(args.output == Output::Json);
+        let mut sandbox = Sandbox::new(config)?;
+
+        // Run the workload
+        match (inline_code, args.script) {
+            (Some(code), _) => {
+                sandbox
+                    .run_code(args.lang.into(), &code, &args.guest_args)
+                    .await
+            }
+            (None, Some(script)) => sandbox.run_with_args(&script, &args.guest_args).await,
+            (None, None) => unreachable!("clap requires a script, --eval or --code"),
+        }
+    }
+    .await;
+    (workload_type, result)
+}
+
+/// JSON object describing the outcome of a run for `--output json`
+fn run_report(
+    workload_type: Option<WorkloadType>,
+    result: &Result<RunResult>,
+) -> serde_json::Value {
+    // A run that failed in the guest still reports what it produced
+    let (run, error) = match result {
+        Ok(run) => (Some(run), None),
+        Err(e) => (
+            e.downcast_ref::<RunFailure>()
+                .map(|failure| &failure.result),
+            Some(e.to_string()),
+        ),
+    };
+    let binary = |version: Option<&BinaryVersion>| {
+        version.map(|version| {
+            serde_json::json!({
+                "path": version.path,
+                "release": version.release,
+                "sha256": version.sha256,
+            })
+        })
+    };
+    serde_json::json!({
+        "status": if error.is_none() { "success" } else { "failure" },
+        "error": error,
+        "workload_type": workload_type.map(|workload_type| match workload_type {
+            WorkloadType::JavaScript => "javascript",
+            WorkloadType::Python => "python",
+            WorkloadType::Binary => "binary",
+        }),
+        "stdout": run.map(|run| String::from_utf8_lossy(&run.stdout).into_owned()),
+        "stderr": run.map(|run| String::from_utf8_lossy(&run.stderr).into_owned()),
+        "output_truncated": run.is_some_and(|run| run.output_truncated),
+        "duration_ms": run.map(|run| run.duration.as_millis() as u64),
+        "kernel": binary(run.and_then(|run| run.kernel.as_ref())),
+        "interpreter": binary(run.and_then(|run| run.interpreter.as_ref())),
+        "policy_violations": run.map_or(Vec::new(), |run| {
+            run.violations
+                .iter()
+                .map(|violation| {
+                    serde_json::json!({
+                        "syscall": violation.syscall,
+                        "path": violation.path,
+                        "errno": violation.errno,
+                    })
+                })
+                .collect()
+        }),
+    })
+}
+
+/// Outcome of a workload run by `batch`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BatchStatus {
+    Pass,
+    Fail,
+    Timeout,
+}
+
+impl BatchStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            BatchStatus::Pass => "pass",
+            BatchStatus::Fail => "fail",
+            BatchStatus::Timeout => "timeout",
+        }
+    }
+}
 
-    // Check for flags
-    let verbose = args.contains(&"--verbose".to_string());
-    let setup_registry = args.contains(&"--setup-registry".to_string());
-    let clear_registry = args.contains(&"--clear-registry".to_string());
+/// Result of one workload of a batch
+struct BatchOutcome {
+    file: PathBuf,
+    status: BatchStatus,
+    duration: Duration,
+    error: Option<String>,
+    stdout: String,
+    stderr: String,
+    log_dir: PathBuf,
+}
 
-    // Handle setup-registry command
-    if setup_registry {
-        return setup_registry_command().await;
+async fn batch_command(
+    global: &GlobalArgs,
+    args: BatchArgs,
+    defaults: &RuntimeConfig,
+) -> Result<()> {
+    let files = batch_workloads(&args.workloads)?;
+    if files.is_empty() {
+        anyhow::bail!("No workloads found in {}", args.workloads);
     }
 
-    // Handle clear-registry command
-    if clear_registry {
-        return clear_registry_command().await;
+    // Each workload runs in its own process, with its own tmp and log directories
+    let tmp_dir = args
+        .sandbox
+        .tmp_dir
+        .as_deref()
+        .unwrap_or(&defaults.tmp_directory);
+    let base_dir = Path::new(tmp_dir).join(format!("batch-{}", std::process::id()));
+    let exe = std::env::current_exe()?;
+    let mut flags = global.to_flags();
+    flags.push("run".to_string());
+    flags.extend(args.sandbox.to_flags());
+    let timeout = Duration::from_secs(args.timeout);
+
+    let jobs = match args.jobs {
+        Some(jobs) => jobs.max(1),
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(jobs));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, file) in files.iter().cloned().enumerate() {
+        let semaphore = semaphore.clone();
+        let exe = exe.clone();
+        let flags = flags.clone();
+        let work_dir = base_dir.join(index.to_string());
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let outcome = run_batch_workload(&exe, &flags, file, &work_dir, timeout).await;
+            (index, outcome)
+        });
     }
 
-    // Find the script argument (first non-flag argument)
-    let script_arg = args
+    let total = files.len();
+    let mut outcomes = Vec::with_capacity(total);
+    while let Some(joined) = tasks.join_next().await {
+        let (index, outcome) = joined?;
+        eprintln!(
+            "[{}/{}] {:<7} {} ({:.2}s)",
+            outcomes.len() + 1,
+            total,
+            outcome.status.as_str().to_uppercase(),
+            outcome.file.display(),
+            outcome.duration.as_secs_f64()
+        );
+        outcomes.push((index, outcome));
+    }
+    outcomes.sort_by_key(|(index, _)| *index);
+    let outcomes: Vec<BatchOutcome> = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+
+    // Summary table
+    let width = outcomes
         .iter()
-        .position(|arg| !arg.starts_with("--") && !arg.ends_with("hyperlight-nanvix"));
+        .map(|outcome| outcome.file.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!("\n{:<width$}  {:<8} {:>9}", "FILE", "STATUS", "DURATION");
+    for outcome in &outcomes {
+        println!(
+            "{:<width$}  {:<8} {:>8.2}s",
+            outcome.file.display(),
+            outcome.status.as_str(),
+            outcome.duration.as_secs_f64()
+        );
+    }
+    let count = |status| {
+        outcomes
+            .iter()
+            .filter(|outcome| outcome.status == status)
+            .count()
+    };
+    let (passed, failed, timed_out) = (
+        count(BatchStatus::Pass),
+        count(BatchStatus::Fail),
+        count(BatchStatus::Timeout),
+    );
+    println!(
+        "\n{} passed, {} failed, {} timed out; logs in {}",
+        passed,
+        failed,
+        timed_out,
+        base_dir.display()
+    );
+
+    if let Some(path) = &args.junit {
+        std::fs::write(path, junit_report(&outcomes))?;
+    }
+    if let Some(path) = &args.json {
+        std::fs::write(path, serde_json::to_string_pretty(&json_report(&outcomes))?)?;
+    }
 
-    let script_path = if let Some(idx) = script_arg {
-        Path::new(&args[idx])
+    if failed + timed_out > 0 {
+        anyhow::bail!("{} of {} workloads did not pass", failed + timed_out, total);
+    }
+    Ok(())
+}
+
+/// Workloads in a directory, or the files matching a glob, sorted by path
+///
+/// Files in a directory are selected by extension; a glob may also match
+/// compiled binaries without one.
+fn batch_workloads(pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let mut files: Vec<PathBuf> = if path.is_dir() {
+        std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .filter(|file| file.as_ref().is_ok_and(|file| file.extension().is_some()))
+            .collect::<std::io::Result<_>>()?
     } else {
-        eprintln!("Usage: {} [--verbose] <script_path>", args[0]);
-        eprintln!("       {} --setup-registry", args[0]);
-        eprintln!("       {} --clear-registry", args[0]);
-        eprintln!("Supported file types: .js, .mjs (JavaScript), .py (Python), .elf, .o (Binary)");
-        eprintln!("Options:");
-        eprintln!("  --verbose         Show detailed nanvix logging");
-        eprintln!("  --setup-registry  Download nanvix registry and show compilation instructions");
-        eprintln!("  --clear-registry  Clear the nanvix registry cache");
-        std::process::exit(1);
+        glob::glob(pattern)?.collect::<Result<_, _>>()?
+    };
+    files.retain(|file| file.is_file() && WorkloadType::from_path(file).is_some());
+    files.sort();
+    Ok(files)
+}
+
+/// Run one workload of a batch as `hyperlight-nanvix run --output json`
+async fn run_batch_workload(
+    exe: &Path,
+    flags: &[String],
+    file: PathBuf,
+    work_dir: &Path,
+    timeout: Duration,
+) -> BatchOutcome {
+    let tmp_dir = work_dir.join("tmp");
+    let log_dir = work_dir.join("log");
+    let started = Instant::now();
+    let mut outcome = BatchOutcome {
+        file,
+        status: BatchStatus::Fail,
+        duration: Duration::ZERO,
+        error: None,
+        stdout: String::new(),
+        stderr: String::new(),
+        log_dir: log_dir.clone(),
+    };
+
+    let child = tokio::process::Command::new(exe)
+        .args(flags)
+        .arg("--output")
+        .arg("json")
+        .arg("--tmp-dir")
+        .arg(&tmp_dir)
+        .arg("--log-dir")
+        .arg(&log_dir)
+        .arg(&outcome.file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let output = match child {
+        Ok(child) => tokio::time::timeout(timeout, child.wait_with_output()).await,
+        Err(e) => Ok(Err(e)),
     };
+    outcome.duration = started.elapsed();
 
-    // Check if file exists
-    if !script_path.exists() {
-        eprintln!("Error: File {:?} does not exist", script_path);
-        std::process::exit(1);
+    match output {
+        Err(_) => {
+            outcome.status = BatchStatus::Timeout;
+            outcome.error = Some(format!("timed out after {}s", timeout.as_secs()));
+        }
+        Ok(Err(e)) => outcome.error = Some(format!("failed to start workload: {}", e)),
+        Ok(Ok(output)) => match serde_json::from_slice::<serde_json::Value>(&output.stdout) {
+            Ok(report) => {
+                if report["status"] == "success" {
+                    outcome.status = BatchStatus::Pass;
+                }
+                outcome.error = report["error"].as_str().map(str::to_string);
+                outcome.stdout = report["stdout"].as_str().unwrap_or_default().to_string();
+                outcome.stderr = report["stderr"].as_str().unwrap_or_default().to_string();
+            }
+            // Usage errors are reported by clap on stderr
+            Err(_) => {
+                outcome.error = Some(String::from_utf8_lossy(&output.stderr).trim().to_string())
+            }
+        },
+    }
+
+    if let Err(e) = std::fs::remove_dir_all(&tmp_dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Warning: failed to remove {}: {}", tmp_dir.display(), e);
+        }
     }
+    outcome
+}
+
+/// JUnit XML report of a batch, one test case per workload
+fn junit_report(outcomes: &[BatchOutcome]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let failures = outcomes
+        .iter()
+        .filter(|outcome| outcome.status != BatchStatus::Pass)
+        .count();
+    let time: f64 = outcomes
+        .iter()
+        .map(|outcome| outcome.duration.as_secs_f64())
+        .sum();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuite name=\"hyperlight-nanvix\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\">\n",
+        outcomes.len(),
+        failures,
+        time
+    ));
+    for outcome in outcomes {
+        xml.push_str(&format!(
+            "  <testcase classname=\"hyperlight-nanvix.batch\" name=\"{}\" time=\"{:.3}\">\n",
+            escape(&outcome.file.display().to_string()),
+            outcome.duration.as_secs_f64()
+        ));
+        if outcome.status != BatchStatus::Pass {
+            xml.push_str(&format!(
+                "    <failure type=\"{}\" message=\"{}\"/>\n",
+                outcome.status.as_str(),
+                escape(outcome.error.as_deref().unwrap_or_default())
+            ));
+        }
+        if !outcome.stdout.is_empty() {
+            xml.push_str(&format!(
+                "    <system-out>{}</system-out>\n",
+                escape(&outcome.stdout)
+            ));
+        }
+        if !outcome.stderr.is_empty() {
+            xml.push_str(&format!(
+                "    <system-err>{}</system-err>\n",
+                escape(&outcome.stderr)
+            ));
+        }
+        xml.push_str("  </testcase>\n");
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+/// JSON report of a batch
+fn json_report(outcomes: &[BatchOutcome]) -> serde_json::Value {
+    let count = |status| {
+        outcomes
+            .iter()
+            .filter(|outcome| outcome.status == status)
+            .count()
+    };
+    serde_json::json!({
+        "total": outcomes.len(),
+        "passed": count(BatchStatus::Pass),
+        "failed": count(BatchStatus::Fail),
+        "timed_out": count(BatchStatus::Timeout),
+        "results": outcomes
+            .iter()
+            .map(|outcome| {
+                serde_json::json!({
+                    "file": outcome.file,
+                    "status": outcome.status.as_str(),
+                    "duration_ms": outcome.duration.as_millis() as u64,
+                    "error": outcome.error,
+                    "stdout": outcome.stdout,
+                    "stderr": outcome.stderr,
+                    "log_dir": outcome.log_dir,
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Largest request `serve` accepts, including uploaded files
+const MAX_REQUEST_SIZE: usize = 64 * 1024 * 1024;
+
+/// State shared by the requests of `serve`
+struct ServeState {
+    exe: PathBuf,
+    /// Command-line flags of the `run` command each request is run with
+    flags: Vec<String>,
+    /// Directory holding the workload, tmp and log directories of each request
+    base_dir: PathBuf,
+    /// Default and longest timeout of a run
+    timeout: Duration,
+    /// Requests being read, waiting for a worker or running
+    slots: tokio::sync::Semaphore,
+    workers: tokio::sync::Semaphore,
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+/// A request to `POST /v1/run`, sent as JSON or as a multipart form with an uploaded `file`
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RunRequest {
+    /// `js` or `python`; detected from the name of an uploaded file when absent
+    language: Option<String>,
+    code: Option<String>,
+    args: Vec<String>,
+    stdin: Option<String>,
+    /// Seconds, at most the server's timeout
+    timeout: Option<u64>,
+    /// Name and contents of an uploaded file
+    #[serde(skip)]
+    file: Option<(String, Vec<u8>)>,
+}
+
+async fn serve_command(
+    global: &GlobalArgs,
+    args: ServeArgs,
+    defaults: &RuntimeConfig,
+) -> Result<()> {
+    // Like batch, each request runs in its own worker process, there is no pool
+    // of runtimes: syscall middleware is installed process-wide, and a worker
+    // that times out can be killed.
+    let tmp_dir = args
+        .sandbox
+        .tmp_dir
+        .as_deref()
+        .unwrap_or(&defaults.tmp_directory);
+    let base_dir = Path::new(tmp_dir).join(format!("serve-{}", std::process::id()));
+    let mut flags = global.to_flags();
+    flags.push("run".to_string());
+    flags.extend(args.sandbox.to_flags());
+    let max_concurrency = match args.max_concurrency {
+        Some(max_concurrency) => max_concurrency.max(1),
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let max_queue = args.max_queue.unwrap_or(4 * max_concurrency);
+    let state = Arc::new(ServeState {
+        exe: std::env::current_exe()?,
+        flags,
+        base_dir: base_dir.clone(),
+        timeout: Duration::from_secs(args.timeout.max(1)),
+        slots: tokio::sync::Semaphore::new(max_concurrency + max_queue),
+        workers: tokio::sync::Semaphore::new(max_concurrency),
+        next_id: std::sync::atomic::AtomicU64::new(0),
+    });
+
+    let app = axum::Router::new()
+        .route("/v1/run", axum::routing::post(run_handler))
+        .layer(DefaultBodyLimit::max(MAX_REQUEST_SIZE))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(args.listen)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", args.listen, e))?;
+    println!(
+        "Listening on http://{} ({} concurrent runs, {} queued)",
+        listener.local_addr()?,
+        max_concurrency,
+        max_queue
+    );
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    if let Err(e) = std::fs::remove_dir_all(&base_dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Warning: failed to remove {}: {}", base_dir.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Handle `POST /v1/run`, answering with the report of `run --output json`
+async fn run_handler(
+    State(state): State<Arc<ServeState>>,
+    request: axum::extract::Request,
+) -> (StatusCode, Json<serde_json::Value>) {
+    // Bodies are only read for requests the queue has room for
+    let Ok(_slot) = state.slots.try_acquire() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "too many requests are waiting" })),
+        );
+    };
+    let multipart = request
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    let parsed = if multipart {
+        match Multipart::from_request(request, &()).await {
+            Ok(multipart) => multipart_request(multipart).await,
+            Err(rejection) => Err(anyhow::anyhow!(rejection.body_text())),
+        }
+    } else {
+        match Json::<RunRequest>::from_request(request, &()).await {
+            Ok(Json(run)) => Ok(run),
+            Err(rejection) => Err(anyhow::anyhow!(rejection.body_text())),
+        }
+    };
+    match parsed {
+        Ok(run) => serve_run(&state, run).await,
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Read a run request from a multipart form
+async fn multipart_request(mut multipart: Multipart) -> Result<RunRequest> {
+    let mut request = RunRequest::default();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" => {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+                request.file = Some((file_name, field.bytes().await?.to_vec()));
+            }
+            "language" => request.language = Some(field.text().await?),
+            "code" => request.code = Some(field.text().await?),
+            "args" => request.args.push(field.text().await?),
+            "stdin" => request.stdin = Some(field.text().await?),
+            "timeout" => {
+                let timeout = field.text().await?;
+                let timeout = timeout
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid timeout {:?}", timeout))?;
+                request.timeout = Some(timeout);
+            }
+            _ => anyhow::bail!("Unknown field {:?}", name),
+        }
+    }
+    Ok(request)
+}
+
+/// Write the code or file of `request` into `work_dir`, returning its path
+fn stage_request(request: &mut RunRequest, work_dir: &Path) -> Result<PathBuf> {
+    let lang = match &request.language {
+        Some(language) => Some(Lang::from_str(language, true).map_err(|_| {
+            anyhow::anyhow!("Unsupported language {:?}, expected js or python", language)
+        })?),
+        None => None,
+    };
+    let script_name = |lang: Lang| format!("main.{}", WorkloadType::from(lang).extensions()[0]);
+    let (name, contents) = match (request.code.take(), request.file.take()) {
+        (Some(code), None) => (script_name(lang.unwrap_or(Lang::Js)), code.into_bytes()),
+        (None, Some((file_name, contents))) => {
+            let name = match lang {
+                Some(lang) => script_name(lang),
+                // Only the name of the upload is kept, never its directories
+                None => Path::new(&file_name)
+                    .file_name()
+                    .map_or("workload".to_string(), |name| {
+                        name.to_string_lossy().into_owned()
+                    }),
+            };
+            (name, contents)
+        }
+        (Some(_), Some(_)) => anyhow::bail!("Give either code or a file to run, not both"),
+        (None, None) => anyhow::bail!("Give the code to run or upload a file"),
+    };
+    std::fs::create_dir_all(work_dir)?;
+    let path = work_dir.join(name);
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// Run a request in a worker process once one of the `serve` workers is free.
+///
+/// The request is staged only once it has a worker, and a run that exceeds its
+/// timeout is stopped by killing the worker process.
+async fn serve_run(
+    state: &ServeState,
+    mut request: RunRequest,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let id = state
+        .next_id
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let work_dir = state.base_dir.join(id.to_string());
+    let response = async {
+        let Ok(_permit) = state.workers.acquire().await else {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({ "error": "the server is shutting down" }),
+            );
+        };
+        let workload = match stage_request(&mut request, &work_dir) {
+            Ok(workload) => workload,
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    serde_json::json!({ "error": e.to_string() }),
+                )
+            }
+        };
+        let timeout = request.timeout.map_or(state.timeout, |secs| {
+            Duration::from_secs(secs.max(1)).min(state.timeout)
+        });
+
+        let child = tokio::process::Command::new(&state.exe)
+            .args(&state.flags)
+            .arg("--output")
+            .arg("json")
+            .arg("--tmp-dir")
+            .arg(work_dir.join("tmp"))
+            .arg("--log-dir")
+            .arg(work_dir.join("log"))
+            .arg(&workload)
+            .arg("--")
+            .args(&request.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    serde_json::json!({ "error": format!("failed to start workload: {}", e) }),
+                )
+            }
+        };
+        // The guest reads its standard input from the worker's
+        if let Some(mut stdin) = child.stdin.take() {
+            let input = request.stdin.take().unwrap_or_default();
+            tokio::spawn(async move {
+                use tokio::io::AsyncWriteExt;
+                let _ = stdin.write_all(input.as_bytes()).await;
+            });
+        }
+
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let output = async {
+            use tokio::io::AsyncReadExt;
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            tokio::try_join!(stdout.read_to_end(&mut out), stderr.read_to_end(&mut err))?;
+            Ok::<_, std::io::Error>(std::process::Output {
+                status: child.wait().await?,
+                stdout: out,
+                stderr: err,
+            })
+        };
+        let output = tokio::time::timeout(timeout, output).await;
+        if output.is_err() {
+            // The guest runs inside the worker, so killing the worker stops it
+            let _ = child.kill().await;
+        }
+        match output {
+            Err(_) => (
+                StatusCode::OK,
+                serde_json::json!({
+                    "status": "timeout",
+                    "error": format!("timed out after {}s", timeout.as_secs()),
+                }),
+            ),
+            Ok(Err(e)) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                serde_json::json!({ "error": format!("failed to run workload: {}", e) }),
+            ),
+            Ok(Ok(output)) => match serde_json::from_slice(&output.stdout) {
+                Ok(report) => (StatusCode::OK, report),
+                // Usage errors are reported by clap on stderr
+                Err(_) => (
+                    StatusCode::BAD_REQUEST,
+                    serde_json::json!({
+                        "error": String::from_utf8_lossy(&output.stderr).trim(),
+                    }),
+                ),
+            },
+        }
+    }
+    .await;
+
+    if let Err(e) = std::fs::remove_dir_all(&work_dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Warning: failed to remove {}: {}", work_dir.display(), e);
+        }
+    }
+    (response.0, Json(response.1))
+}
+
+async fn repl_command(
+    global: &GlobalArgs,
+    lang: Lang,
+    args: SandboxArgs,
+    defaults: &RuntimeConfig,
+) -> Result<()> {
+    let workload_type = WorkloadType::from(lang);
+    let config = sandbox_config(global, args, Some(workload_type), defaults)?;
+    let mut sandbox = Sandbox::new(config)?;
+
+    sandbox
+        .repl(workload_type, Box::new(Editor(DefaultEditor::new()?)))
+        .await
+        .map_err(|e| anyhow::anyhow!("Error running REPL: {}", e))?;
+    Ok(())
+}
+
+/// Host-side line editing for the REPL
+struct Editor(DefaultEditor);
+
+impl LineReader for Editor {
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        match self.0.readline(prompt) {
+            Ok(line) => {
+                let _ = self.0.add_history_entry(line.as_str());
+                Some(line)
+            }
+            // Ctrl-C discards the line being typed
+            Err(ReadlineError::Interrupted) => Some(String::new()),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Runtime configuration for the sandbox options given on the command line
+fn sandbox_config(
+    global: &GlobalArgs,
+    args: SandboxArgs,
+    workload_type: Option<WorkloadType>,
+    defaults: &RuntimeConfig,
+) -> Result<RuntimeConfig> {
+    let mut config = defaults
+        .clone()
+        .with_artifact_source(artifact_source(global)?)
+        .with_progress(progress_bar());
+    if let Some(dir) = args.log_dir {
+        config = config.with_log_directory(dir);
+    }
+    if let Some(dir) = args.tmp_dir {
+        config = config.with_tmp_directory(dir);
+    }
+    if let Some(dir) = args.offline_dir {
+        config = config.with_offline_directory(dir);
+    }
+    if args.fast_verify {
+        config = config.with_verification(Verification::Fast);
+    }
+    if let Some(path) = args.kernel {
+        config = config.with_kernel_path(path);
+    }
+    if let (Some(path), Some(workload_type)) = (args.interpreter, workload_type) {
+        config = config.with_interpreter_path(workload_type, path);
+    }
+    Ok(config)
+}
+
+/// Source selected with --mirror, or the upstream registry
+fn artifact_source(global: &GlobalArgs) -> Result<Arc<dyn ArtifactSource>> {
+    Ok(match &global.mirror {
+        Some(url) => Arc::new(MirrorSource::new(url)?),
+        None => Arc::new(RegistrySource),
+    })
+}
+
+async fn execute(cli: Cli) -> Result<()> {
+    let global = cli.global;
+
+    // Settings from the configuration file and the environment, overridden by flags
+    let mut defaults = match &global.config {
+        Some(path) => RuntimeConfig::from_file(path)?,
+        None => RuntimeConfig::discover()?,
+    };
+    if let Some(dir) = &global.cache_dir {
+        defaults = defaults.with_cache_directory(dir.to_string_lossy());
+    }
+    if let Some(release) = &global.release {
+        defaults = defaults.with_nanvix_release(release);
+    }
+    let cache_dir = defaults.resolve_cache_directory();
+
+    // Pinned releases are cached in their own directory
+    let registry_dir = defaults.resolve_release_directory();
 
     // Initialize nanvix logging only when --verbose is specified
-    if verbose {
+    if global.verbose {
         log::init(
             false,
             DEFAULT_LOG_LEVEL,
-            "/tmp/hyperlight-nanvix".to_string(),
+            defaults.log_directory.clone(),
             None,
         );
     }
 
-    // Create runtime configuration
-    let config = RuntimeConfig::new()
-        .with_log_directory("/tmp/hyperlight-nanvix")
-        .with_tmp_directory("/tmp/hyperlight-nanvix");
+    let command = cli.command.unwrap_or(Command::Run(cli.run));
 
-    // Create Sandbox instance
-    let mut sandbox = Sandbox::new(config)?;
+    match command {
+        Command::Run(args) => run_command(&global, args, &defaults).await,
+        Command::Repl { lang, sandbox } => repl_command(&global, lang, sandbox, &defaults).await,
+        Command::Registry(command) => {
+            let mut source = artifact_source(&global)?;
+            if let Some(release) = &defaults.nanvix_release {
+                source = source.release(release)?;
+            }
+            registry_command(command, &registry_dir, source.as_ref()).await
+        }
+        Command::Cache(command) => cache_command(command, &cache_dir),
+        Command::Batch(args) => batch_command(&global, args, &defaults).await,
+        Command::Serve(args) => serve_command(&global, args, &defaults).await,
+        Command::Doctor(sandbox) => doctor_command(&global, sandbox, &defaults),
+        Command::Version => {
+            println!(
+                "hyperlight-nanvix {} (nanvix {})",
+                env!("CARGO_PKG_VERSION"),
+                hyperlight_nanvix::diagnose::NANVIX_REVISION
+            );
+            Ok(())
+        }
+    }
+}
 
-    // Run the workload
-    match sandbox.run(script_path).await {
-        Ok(()) => {}
-        Err(e) => {
-            eprintln!("Error running workload: {}", e);
-            std::process::exit(1);
+/// Deprecated flags, kept as hidden aliases of their subcommands
+const DEPRECATED_FLAGS: &[(&str, &str)] =
+    &[("--setup-registry", "setup"), ("--clear-registry", "clear")];
+
+/// Rewrite `--setup-registry` and `--clear-registry` into `registry setup` and
+/// `registry clear`, keeping the other arguments (e.g. `--only`)
+fn rewrite_deprecated_flags(mut args: Vec<OsString>) -> Vec<OsString> {
+    // Arguments after `--` belong to the guest
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    for (flag, subcommand) in DEPRECATED_FLAGS {
+        if let Some(index) = args[..end].iter().position(|arg| arg == flag) {
+            eprintln!(
+                "warning: {} is deprecated, use `hyperlight-nanvix registry {}`",
+                flag, subcommand
+            );
+            args.remove(index);
+            args.splice(1..1, ["registry".into(), subcommand.into()]);
+            break;
         }
     }
+    args
+}
 
-    Ok(())
+/// Parse the command line, rejecting `run` options given before a subcommand.
+///
+/// Only the global options may precede a subcommand; anything else would be
+/// accepted by the top-level `run` shorthand and then ignored.
+fn parse_cli() -> Cli {
+    let args = rewrite_deprecated_flags(std::env::args_os().collect());
+    let matches = Cli::command().get_matches_from(args);
+    if let Some(subcommand) = matches.subcommand_name() {
+        let mut run_args = RunArgs::augment_args(clap::Command::new("run"));
+        run_args.build();
+        let given = run_args.get_arguments().find(|arg| {
+            matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(arg) = given {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!(
+                        "the argument '{}' cannot be used with the '{}' subcommand",
+                        arg, subcommand
+                    ),
+                )
+                .exit();
+        }
+    }
+    Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Usage errors exit with status 2, help and --version with 0
+    let cli = parse_cli();
+
+    match execute(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,178 @@
+//! Portable registry bundles.
+//!
+//! A bundle is a zstd-compressed tarball of a registry cache: the kernel,
+//! interpreters, Python sysroot and manifest. Exporting one from a provisioned
+//! machine and importing it elsewhere installs the same binaries without
+//! contacting the registry, e.g. when building CI images or offline hosts.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::cache::{self, CacheLock, Integrity, Manifest, Verification};
+
+/// Directories of a registry cache that are bundled, next to the manifest
+const BUNDLED_DIRECTORIES: &[&str] = &["bin", "lib"];
+
+/// zstd compression level of exported bundles
+const COMPRESSION_LEVEL: i32 = 19;
+
+/// Package the registry cache at `cache_dir` into the bundle `output`
+pub fn export_bundle(cache_dir: &Path, output: &Path) -> Result<()> {
+    let _lock = CacheLock::acquire(cache_dir)?;
+
+    // Every bundled binary carries its digest
+    let mut manifest = Manifest::load(cache_dir)?;
+    let bin_dir = cache::get_binary_cache_directory(cache_dir);
+    if !bin_dir.is_dir() {
+        anyhow::bail!("No cached binaries in {}", cache_dir.display());
+    }
+    for entry in std::fs::read_dir(&bin_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_file() && !manifest.binaries.contains_key(&name) {
+            manifest.record(&name, &entry.path())?;
+        }
+    }
+    manifest.last_used = None;
+
+    let dir = output.parent().unwrap_or(Path::new("."));
+    let temp = dir.join(cache::temp_name(".bundle"));
+    let result = write_bundle(cache_dir, &manifest, &temp);
+    if let Err(error) = result.and_then(|()| Ok(std::fs::rename(&temp, output)?)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(error).with_context(|| format!("Failed to export bundle {:?}", output));
+    }
+    Ok(())
+}
+
+fn write_bundle(cache_dir: &Path, manifest: &Manifest, output: &Path) -> Result<()> {
+    let file = std::fs::File::create(output)?;
+    let encoder = zstd::Encoder::new(file, COMPRESSION_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.follow_symlinks(false);
+    for dir in BUNDLED_DIRECTORIES {
+        let path = cache_dir.join(dir);
+        if path.is_dir() {
+            builder.append_dir_all(dir, &path)?;
+        }
+    }
+
+    let contents = serde_json::to_vec_pretty(manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, cache::MANIFEST_FILE, contents.as_slice())?;
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Install the bundle `bundle` into the registry cache at `cache_dir`.
+///
+/// Every binary is checked against the bundle's manifest before anything is
+/// installed; bundled files replace the cached ones.
+pub fn import_bundle(bundle: &Path, cache_dir: &Path) -> Result<()> {
+    let _lock = CacheLock::acquire(cache_dir)?;
+
+    let staging = cache_dir.join(cache::temp_name(".import"));
+    let result = import_staged(bundle, &staging, cache_dir);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    result.with_context(|| format!("Failed to import bundle {:?}", bundle))
+}
+
+fn import_staged(bundle: &Path, staging: &Path, cache_dir: &Path) -> Result<()> {
+    let file = std::fs::File::open(bundle)?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+    std::fs::create_dir_all(staging)?;
+    // Entries escaping the staging directory are rejected by `unpack`
+    archive.unpack(staging)?;
+
+    let bundled = Manifest::load(staging)?;
+    if bundled.binaries.is_empty() {
+        anyhow::bail!("Bundle has no manifest of its binaries");
+    }
+    let bin_dir = cache::get_binary_cache_directory(staging);
+    for name in bundled.binaries.keys() {
+        let path = bin_dir.join(name);
+        if !path.is_file() {
+            anyhow::bail!("Bundle is missing {}", name);
+        }
+        if let Integrity::Mismatch { expected, actual } =
+            bundled.verify(name, &path, Verification::Full)?
+        {
+            anyhow::bail!(
+                "{} failed integrity verification: expected sha256 {}, found {}",
+                name,
+                expected,
+                actual
+            );
+        }
+    }
+
+    let mut manifest = Manifest::load(cache_dir)?;
+    for dir in BUNDLED_DIRECTORIES {
+        let staged = staging.join(dir);
+        if staged.is_dir() {
+            replace_tree(&staged, &cache_dir.join(dir))?;
+        }
+    }
+    let cached_bin_dir = cache::get_binary_cache_directory(cache_dir);
+    for name in bundled.binaries.keys() {
+        manifest.record(name, &cached_bin_dir.join(name))?;
+    }
+    manifest.save(cache_dir)
+}
+
+/// Move the contents of `staged` into `dest`, replacing existing files
+fn replace_tree(staged: &Path, dest: &Path) -> Result<()> {
+    std::fs::create_dir_all(dest)?;
+    for entry in std::fs::read_dir(staged)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            replace_tree(&entry.path(), &target)?;
+        } else {
+            if target.is_dir() {
+                std::fs::remove_dir_all(&target)?;
+            }
+            std::fs::rename(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_registry_bundle_roundtrip() {
+        let root = TempDir::new("bundle");
+        let source = root.join("source");
+        std::fs::create_dir_all(source.join("bin")).unwrap();
+        std::fs::create_dir_all(source.join("lib/python3.12")).unwrap();
+        std::fs::write(source.join("bin/kernel.elf"), b"kernel").unwrap();
+        std::fs::write(source.join("bin/qjs"), b"qjs").unwrap();
+        std::fs::write(source.join("lib/python3.12/os.py"), b"import sys").unwrap();
+
+        let bundle_path = root.join("bundle.tar.zst");
+        export_bundle(&source, &bundle_path).unwrap();
+
+        let target = root.join("target");
+        import_bundle(&bundle_path, &target).unwrap();
+        assert_eq!(std::fs::read(target.join("bin/qjs")).unwrap(), b"qjs");
+        assert_eq!(
+            std::fs::read(target.join("lib/python3.12/os.py")).unwrap(),
+            b"import sys"
+        );
+        assert!(cache::verify_binaries(&target)
+            .unwrap()
+            .iter()
+            .all(|(_, integrity)| *integrity == cache::Integrity::Valid));
+        assert_eq!(cache::list_binaries(&target).unwrap().len(), 2);
+    }
+}
@@ -1,21 +1,89 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use nanvix::log;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::runtime::WorkloadType;
+use crate::source::{ArtifactSource, ProgressCallback};
+
+/// Environment variable overriding the cache directory
+pub const CACHE_DIRECTORY_ENV: &str = "HYPERLIGHT_NANVIX_CACHE";
+
+/// Name of the registry cache below the user's cache directory
+const CACHE_DIRECTORY_NAME: &str = "nanvix-registry";
+
+/// Kernel binary booted by every sandbox
+pub const KERNEL_BINARY: &str = "kernel.elf";
+
+/// Binaries the upstream registry publishes for hyperlight single-process sandboxes
+pub const KNOWN_BINARIES: &[&str] = &[KERNEL_BINARY, "python3", "qjs"];
+
+/// Python sysroot (standard library), relative to the registry root
+pub const PYTHON_SYSROOT: &str = "lib/python3.12";
+
+/// Manifest of the digests of cached binaries, relative to the registry root
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Lock file serializing downloads and manifest updates, relative to the registry root
+const LOCK_FILE: &str = ".lock";
 
 /// Get the default cache directory for nanvix registry
+///
+/// The first of these that is set wins: `$HYPERLIGHT_NANVIX_CACHE`,
+/// `$XDG_CACHE_HOME/nanvix-registry`, `$HOME/.cache/nanvix-registry`. When none
+/// is set (e.g. in containers or system services), the cache lives in the
+/// system temporary directory.
 pub fn get_cache_directory() -> PathBuf {
-    let home_dir = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-    std::path::Path::new(&home_dir)
-        .join(".cache")
-        .join("nanvix-registry")
+    let env_dir = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+
+    if let Some(dir) = env_dir(CACHE_DIRECTORY_ENV) {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env_dir("XDG_CACHE_HOME") {
+        Path::new(&dir).join(CACHE_DIRECTORY_NAME)
+    } else if let Some(dir) = env_dir("HOME") {
+        Path::new(&dir).join(".cache").join(CACHE_DIRECTORY_NAME)
+    } else {
+        std::env::temp_dir().join(CACHE_DIRECTORY_NAME)
+    }
+}
+
+/// Directory of a pinned Nanvix release inside the cache
+pub fn get_release_directory(cache_dir: &Path, release: &str) -> PathBuf {
+    cache_dir.join("releases").join(release)
+}
+
+/// Pinned releases present in the cache, sorted by name
+pub fn list_releases(cache_dir: &Path) -> Result<Vec<String>> {
+    let releases_dir = cache_dir.join("releases");
+    if !releases_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut releases = Vec::new();
+    for entry in std::fs::read_dir(&releases_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            releases.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    releases.sort();
+    Ok(releases)
 }
 
 /// Get the binary cache directory
-pub fn get_binary_cache_directory() -> PathBuf {
-    get_cache_directory().join("bin")
+pub fn get_binary_cache_directory(cache_dir: &Path) -> PathBuf {
+    cache_dir.join("bin")
 }
 
 /// Check if a binary exists in the cache and return its path if found
-pub async fn get_cached_binary_path(binary_name: &str) -> Option<String> {
-    let cache_path = get_binary_cache_directory().join(binary_name);
+pub async fn get_cached_binary_path(cache_dir: &Path, binary_name: &str) -> Option<String> {
+    let cache_path = get_binary_cache_directory(cache_dir).join(binary_name);
 
     if tokio::fs::metadata(&cache_path).await.is_ok() {
         Some(cache_path.to_string_lossy().to_string())
@@ -25,7 +93,728 @@ pub async fn get_cached_binary_path(binary_name: &str) -> Option<String> {
 }
 
 /// Check if a binary exists in the cache (synchronous version for setup command)
-pub fn is_binary_cached(binary_name: &str) -> bool {
-    let cache_path = get_binary_cache_directory().join(binary_name);
+pub fn is_binary_cached(cache_dir: &Path, binary_name: &str) -> bool {
+    let cache_path = get_binary_cache_directory(cache_dir).join(binary_name);
     cache_path.exists()
 }
+
+/// Artifacts a workload needs, relative to the registry root
+pub fn required_artifacts(workload_type: WorkloadType) -> Vec<String> {
+    let mut artifacts = vec![format!("bin/{}", KERNEL_BINARY)];
+    match workload_type {
+        WorkloadType::JavaScript => artifacts.push(format!("bin/{}", workload_type.binary_name())),
+        WorkloadType::Python => {
+            artifacts.push(format!("bin/{}", workload_type.binary_name()));
+            artifacts.push(PYTHON_SYSROOT.to_string());
+        }
+        WorkloadType::Binary => {}
+    }
+    artifacts
+}
+
+/// Binaries a set of workloads needs: the kernel and their interpreters
+pub fn required_binaries(workload_types: &[WorkloadType]) -> Vec<&'static str> {
+    let mut binaries = vec![KERNEL_BINARY];
+    for workload_type in workload_types {
+        let name = workload_type.binary_name();
+        if *workload_type != WorkloadType::Binary && !binaries.contains(&name) {
+            binaries.push(name);
+        }
+    }
+    binaries
+}
+
+/// Artifacts a workload needs that are absent from `root`
+pub fn missing_artifacts(root: &Path, workload_type: WorkloadType) -> Vec<String> {
+    required_artifacts(workload_type)
+        .into_iter()
+        .filter(|artifact| !root.join(artifact).exists())
+        .collect()
+}
+
+/// How cached binaries are checked against the manifest before boot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verification {
+    /// Hash every binary before boot
+    #[default]
+    Full,
+    /// Trust binaries whose size and modification time match the manifest, hash the rest
+    Fast,
+    /// Load binaries without checking them
+    Disabled,
+}
+
+/// Outcome of checking a binary against the manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Integrity {
+    /// The binary matches its recorded digest
+    Valid,
+    /// The manifest has no entry for the binary
+    Unrecorded,
+    /// The binary's contents differ from the recorded digest
+    Mismatch { expected: String, actual: String },
+}
+
+/// Recorded state of a cached binary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Hex-encoded SHA-256 digest of the contents
+    pub sha256: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Modification time, in seconds since the Unix epoch
+    pub mtime: u64,
+}
+
+/// SHA-256 digests of the binaries in a registry cache
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub binaries: BTreeMap<String, ManifestEntry>,
+    /// Last time a sandbox booted from this cache, in seconds since the Unix epoch
+    #[serde(default)]
+    pub last_used: Option<u64>,
+}
+
+impl Manifest {
+    /// Load the manifest of `cache_dir`; a missing manifest is empty
+    pub fn load(cache_dir: &Path) -> Result<Self> {
+        let path = cache_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read cache manifest {:?}", path))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse cache manifest {:?}", path))
+    }
+
+    /// Write the manifest to `cache_dir`
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(cache_dir)?;
+        let path = cache_dir.join(MANIFEST_FILE);
+        write_atomic(&path, serde_json::to_string_pretty(self)?.as_bytes())
+            .with_context(|| format!("Failed to write cache manifest {:?}", path))
+    }
+
+    /// Record the current contents of the binary `name` at `path`
+    pub fn record(&mut self, name: &str, path: &Path) -> Result<()> {
+        self.record_digest(name, path, sha256_file(path)?)
+    }
+
+    /// Record the binary `name` at `path`, whose digest is already known
+    pub fn record_digest(&mut self, name: &str, path: &Path, sha256: String) -> Result<()> {
+        let (size, mtime) = file_stamp(path)?;
+        let entry = ManifestEntry {
+            sha256,
+            size,
+            mtime,
+        };
+        self.binaries.insert(name.to_string(), entry);
+        Ok(())
+    }
+
+    /// Check the binary `name` at `path` against its recorded digest
+    pub fn verify(&self, name: &str, path: &Path, mode: Verification) -> Result<Integrity> {
+        let Some(entry) = self.binaries.get(name) else {
+            return Ok(Integrity::Unrecorded);
+        };
+        if mode == Verification::Disabled
+            || (mode == Verification::Fast && file_stamp(path)? == (entry.size, entry.mtime))
+        {
+            return Ok(Integrity::Valid);
+        }
+        let actual = sha256_file(path)?;
+        if actual == entry.sha256 {
+            Ok(Integrity::Valid)
+        } else {
+            Ok(Integrity::Mismatch {
+                expected: entry.sha256.clone(),
+                actual,
+            })
+        }
+    }
+}
+
+/// Record the binary `name` at `path` in the manifest of `cache_dir`.
+///
+/// The cache lock is awaited and the binary hashed off the async runtime.
+pub async fn record_binary(cache_dir: &Path, name: &str, path: &Path) -> Result<()> {
+    let (cache_dir, name, path) = (
+        cache_dir.to_path_buf(),
+        name.to_string(),
+        path.to_path_buf(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let _lock = CacheLock::acquire(&cache_dir)?;
+        let mut manifest = Manifest::load(&cache_dir)?;
+        manifest.record(&name, &path)?;
+        manifest.save(&cache_dir)
+    })
+    .await?
+}
+
+/// Record that a sandbox booted from `cache_dir` now
+pub async fn mark_used(cache_dir: &Path) -> Result<()> {
+    let cache_dir = cache_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let _lock = CacheLock::acquire(&cache_dir)?;
+        let mut manifest = Manifest::load(&cache_dir)?;
+        manifest.last_used = Some(now_secs());
+        manifest.save(&cache_dir)
+    })
+    .await?
+}
+
+/// Exclusive lock on a registry cache, shared by all processes using it.
+///
+/// Held while binaries are downloaded and the manifest is updated. The lock is
+/// released when the guard is dropped.
+pub struct CacheLock {
+    _file: std::fs::File,
+}
+
+impl CacheLock {
+    /// Block until the lock on `cache_dir` is acquired
+    pub fn acquire(cache_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(cache_dir)?;
+        let path = cache_dir.join(LOCK_FILE);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open cache lock {:?}", path))?;
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                return Ok(Self { _file: file });
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error).with_context(|| format!("Failed to lock {:?}", path));
+            }
+        }
+    }
+
+    /// Acquire the lock on `cache_dir` without blocking the async runtime
+    pub async fn acquire_async(cache_dir: &Path) -> Result<Self> {
+        let cache_dir = cache_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::acquire(&cache_dir)).await?
+    }
+}
+
+/// Fetch `binary_name` from `source` into `cache_dir` and record its digest.
+///
+/// Concurrent callers, in this process or others, share a single download: the
+/// source fetches into a staging directory under the cache lock, and the
+/// results are renamed into place, so a half-written binary is never visible.
+/// The staging directory is kept when a fetch fails, so the next attempt can
+/// resume it.
+pub async fn fetch_binary(
+    cache_dir: &Path,
+    source: &dyn ArtifactSource,
+    binary_name: &str,
+    progress: Option<&ProgressCallback>,
+) -> Result<PathBuf> {
+    let _lock = CacheLock::acquire_async(cache_dir).await?;
+    fetch_binary_locked(cache_dir, source, binary_name, progress).await
+}
+
+/// Replace a cached binary whose digest is `corrupt_sha256` with a fresh download
+pub async fn refetch_binary(
+    cache_dir: &Path,
+    source: &dyn ArtifactSource,
+    binary_name: &str,
+    corrupt_sha256: &str,
+    progress: Option<&ProgressCallback>,
+) -> Result<PathBuf> {
+    let _lock = CacheLock::acquire_async(cache_dir).await?;
+
+    // Leave the binary alone if another process already replaced it
+    let (dir, name, corrupt_sha256) = (
+        cache_dir.to_path_buf(),
+        binary_name.to_string(),
+        corrupt_sha256.to_string(),
+    );
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let cached = get_binary_cache_directory(&dir).join(&name);
+        if cached.is_file() && sha256_file(&cached)? == corrupt_sha256 {
+            std::fs::remove_file(&cached)?;
+            let mut manifest = Manifest::load(&dir)?;
+            manifest.binaries.remove(&name);
+            manifest.save(&dir)?;
+        }
+        Ok(())
+    })
+    .await??;
+    fetch_binary_locked(cache_dir, source, binary_name, progress).await
+}
+
+async fn fetch_binary_locked(
+    cache_dir: &Path,
+    source: &dyn ArtifactSource,
+    binary_name: &str,
+    progress: Option<&ProgressCallback>,
+) -> Result<PathBuf> {
+    let cached = get_binary_cache_directory(cache_dir).join(binary_name);
+
+    // Another process may have finished the download while we waited
+    let (dir, name, path) = (
+        cache_dir.to_path_buf(),
+        binary_name.to_string(),
+        cached.clone(),
+    );
+    let already_cached = tokio::task::spawn_blocking(move || -> Result<bool> {
+        if !path.is_file() {
+            return Ok(false);
+        }
+        let mut manifest = Manifest::load(&dir)?;
+        if !manifest.binaries.contains_key(&name) {
+            log::warn!(
+                "{} has no recorded digest; trusting it and recording its digest",
+                path.display()
+            );
+            manifest.record(&name, &path)?;
+            manifest.save(&dir)?;
+        }
+        Ok(true)
+    })
+    .await??;
+    if already_cached {
+        return Ok(cached);
+    }
+
+    // Named after the binary, so an interrupted fetch is resumed by the next one
+    let staging = cache_dir.join(format!(".download-{}", binary_name));
+    tokio::fs::create_dir_all(&staging).await?;
+    source
+        .fetch(binary_name, &staging, progress)
+        .await
+        .with_context(|| format!("Failed to fetch {} from {}", binary_name, source.describe()))?;
+
+    let staged = get_binary_cache_directory(&staging).join(binary_name);
+    if !tokio::fs::metadata(&staged)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+    {
+        anyhow::bail!("{} did not provide {}", source.describe(), binary_name);
+    }
+    let expected = source.digest(binary_name).await?;
+    let (dir, name, description) = (
+        cache_dir.to_path_buf(),
+        binary_name.to_string(),
+        source.describe(),
+    );
+    tokio::task::spawn_blocking(move || {
+        install_fetched(&dir, &staging, &name, &description, expected)
+    })
+    .await??;
+    Ok(cached)
+}
+
+/// Verify the binary fetched into `staging` against the `expected` digest,
+/// move the results into `cache_dir` and record the digest
+fn install_fetched(
+    cache_dir: &Path,
+    staging: &Path,
+    binary_name: &str,
+    description: &str,
+    expected: Option<String>,
+) -> Result<()> {
+    let staged = get_binary_cache_directory(staging).join(binary_name);
+    let actual = sha256_file(&staged)?;
+    match expected {
+        Some(expected) if expected != actual => {
+            // Do not resume from a corrupt download
+            std::fs::remove_dir_all(staging)?;
+            anyhow::bail!(
+                "{} from {} failed integrity verification: expected sha256 {}, found {}",
+                binary_name,
+                description,
+                expected,
+                actual
+            );
+        }
+        Some(_) => {}
+        None => log::warn!(
+            "{} publishes no digest for {}; trusting the download and recording its digest",
+            description,
+            binary_name
+        ),
+    }
+
+    let bin_dir = get_binary_cache_directory(cache_dir);
+    let cached = bin_dir.join(binary_name);
+    std::fs::create_dir_all(&bin_dir)?;
+    std::fs::rename(&staged, &cached)
+        .with_context(|| format!("Failed to move {} into the cache", binary_name))?;
+    install_staged(staging, cache_dir)?;
+    std::fs::remove_dir_all(staging)?;
+
+    let mut manifest = Manifest::load(cache_dir)?;
+    manifest.record_digest(binary_name, &cached, actual)?;
+    manifest.save(cache_dir)
+}
+
+/// Move the staged results into `dest`.
+///
+/// A staged sysroot replaces the cached one whole, so a re-fetch leaves no
+/// stale files behind; of the rest, only what `dest` lacks is moved in.
+fn install_staged(staging: &Path, dest: &Path) -> Result<()> {
+    let staged_sysroot = staging.join(PYTHON_SYSROOT);
+    if staged_sysroot.is_dir() {
+        swap_in(&staged_sysroot, &dest.join(PYTHON_SYSROOT))
+            .context("Failed to move the sysroot into the cache")?;
+    }
+    merge_staged(staging, dest)
+}
+
+/// Replace the directory `target` with `staged`.
+///
+/// The old tree is renamed aside before the new one is renamed in, so
+/// `target` never holds a mix of both, and is removed afterwards.
+fn swap_in(staged: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if !target.exists() {
+        std::fs::rename(staged, target)?;
+        return Ok(());
+    }
+    let old = target.with_file_name(temp_name(".old"));
+    std::fs::rename(target, &old)?;
+    if let Err(error) = std::fs::rename(staged, target) {
+        let _ = std::fs::rename(&old, target);
+        return Err(error.into());
+    }
+    std::fs::remove_dir_all(&old)?;
+    Ok(())
+}
+
+/// Move everything in `staging` that `dest` lacks into `dest`
+fn merge_staged(staging: &Path, dest: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(staging)? {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if !target.exists() {
+            std::fs::rename(entry.path(), &target)?;
+        } else if entry.file_type()?.is_dir() && target.is_dir() {
+            merge_staged(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `contents` to a temporary file next to `path`, then rename it into place
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let temp = dir.join(temp_name(".tmp"));
+    std::fs::write(&temp, contents)?;
+    if let Err(error) = std::fs::rename(&temp, path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// A file name unique across threads and processes
+pub(crate) fn temp_name(prefix: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "{}-{}-{}",
+        prefix,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// A binary present in the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedBinary {
+    /// Pinned release the binary belongs to; `None` for the unpinned cache
+    pub release: Option<String>,
+    pub name: String,
+    pub path: PathBuf,
+    /// Size in bytes
+    pub size: u64,
+    /// Recorded SHA-256 digest, if the binary is in the manifest
+    pub sha256: Option<String>,
+    /// Last time a sandbox booted from the binary's release, in seconds since the Unix epoch
+    pub last_used: Option<u64>,
+}
+
+/// List the binaries of the unpinned cache and of every pinned release
+pub fn list_binaries(cache_dir: &Path) -> Result<Vec<CachedBinary>> {
+    let mut roots = vec![(None, cache_dir.to_path_buf())];
+    for release in list_releases(cache_dir)? {
+        let dir = get_release_directory(cache_dir, &release);
+        roots.push((Some(release), dir));
+    }
+
+    let mut binaries = Vec::new();
+    for (release, root) in roots {
+        let bin_dir = get_binary_cache_directory(&root);
+        if !bin_dir.is_dir() {
+            continue;
+        }
+        let manifest = Manifest::load(&root)?;
+        let mut entries = std::fs::read_dir(&bin_dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            binaries.push(CachedBinary {
+                release: release.clone(),
+                sha256: manifest.binaries.get(&name).map(|e| e.sha256.clone()),
+                size: entry.metadata()?.len(),
+                path: entry.path(),
+                last_used: manifest.last_used,
+                name,
+            });
+        }
+    }
+    Ok(binaries)
+}
+
+/// Hash every cached binary and compare it against the manifest
+pub fn verify_binaries(cache_dir: &Path) -> Result<Vec<(CachedBinary, Integrity)>> {
+    let mut results = Vec::new();
+    for binary in list_binaries(cache_dir)? {
+        let root = match &binary.release {
+            Some(release) => get_release_directory(cache_dir, release),
+            None => cache_dir.to_path_buf(),
+        };
+        let integrity =
+            Manifest::load(&root)?.verify(&binary.name, &binary.path, Verification::Full)?;
+        results.push((binary, integrity));
+    }
+    Ok(results)
+}
+
+/// Remove all but the `keep` most recently used pinned releases.
+///
+/// The unpinned cache is never pruned. Returns the removed releases.
+pub fn prune_releases(cache_dir: &Path, keep: usize) -> Result<Vec<String>> {
+    let mut releases = Vec::new();
+    for release in list_releases(cache_dir)? {
+        let dir = get_release_directory(cache_dir, &release);
+        let last_used = Manifest::load(&dir)?.last_used.unwrap_or(0);
+        releases.push((last_used, release));
+    }
+    // Most recently used first; ties keep the newest release name
+    releases.sort_by(|a, b| b.cmp(a));
+
+    let mut removed = Vec::new();
+    for (_, release) in releases.into_iter().skip(keep) {
+        let dir = get_release_directory(cache_dir, &release);
+        std::fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to remove release directory {:?}", dir))?;
+        removed.push(release);
+    }
+    Ok(removed)
+}
+
+/// Hex-encoded SHA-256 digest of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Hex-encoded SHA-256 digest of `contents`
+pub fn sha256_hex(contents: &[u8]) -> String {
+    to_hex(&Sha256::digest(contents))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Size and modification time of a file
+fn file_stamp(path: &Path) -> Result<(u64, u64)> {
+    let metadata = std::fs::metadata(path).with_context(|| format!("Failed to stat {:?}", path))?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    Ok((metadata.len(), mtime))
+}
+
+/// Current time in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::test_util::TempDir;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_offline_directory_missing_artifacts() {
+        let root = TempDir::new("offline");
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::write(root.join("bin/kernel.elf"), b"").unwrap();
+
+        assert!(missing_artifacts(&root, WorkloadType::Binary).is_empty());
+        assert_eq!(
+            missing_artifacts(&root, WorkloadType::Python),
+            vec!["bin/python3", "lib/python3.12"]
+        );
+
+        std::fs::write(root.join("bin/qjs"), b"").unwrap();
+        assert!(missing_artifacts(&root, WorkloadType::JavaScript).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cache_manifest_verification() {
+        let root = TempDir::new("manifest");
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        let kernel = root.join("bin/kernel.elf");
+        std::fs::write(&kernel, b"kernel").unwrap();
+
+        let manifest = Manifest::load(&root).unwrap();
+        assert_eq!(
+            manifest
+                .verify("kernel.elf", &kernel, Verification::Full)
+                .unwrap(),
+            Integrity::Unrecorded
+        );
+
+        record_binary(&root, "kernel.elf", &kernel).await.unwrap();
+        let manifest = Manifest::load(&root).unwrap();
+        assert_eq!(
+            manifest.binaries["kernel.elf"].sha256,
+            "6923dd1bc0460082c5d55a831908c24a282860b7f1cd6c2b79cf1bc8857c639c"
+        );
+        assert_eq!(
+            manifest
+                .verify("kernel.elf", &kernel, Verification::Full)
+                .unwrap(),
+            Integrity::Valid
+        );
+
+        // A truncated binary no longer matches its digest
+        std::fs::write(&kernel, b"kern").unwrap();
+        assert!(matches!(
+            manifest
+                .verify("kernel.elf", &kernel, Verification::Fast)
+                .unwrap(),
+            Integrity::Mismatch { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cache_list_and_prune() {
+        let root = TempDir::new("prune");
+        for (release, last_used) in [("v1.0", 100), ("v1.1", 300), ("v2.0", 200)] {
+            let dir = get_release_directory(&root, release);
+            std::fs::create_dir_all(dir.join("bin")).unwrap();
+            std::fs::write(dir.join("bin/kernel.elf"), release).unwrap();
+            record_binary(&dir, "kernel.elf", &dir.join("bin/kernel.elf"))
+                .await
+                .unwrap();
+            let mut manifest = Manifest::load(&dir).unwrap();
+            manifest.last_used = Some(last_used);
+            manifest.save(&dir).unwrap();
+        }
+
+        let binaries = list_binaries(&root).unwrap();
+        assert_eq!(binaries.len(), 3);
+        assert_eq!(binaries[0].release.as_deref(), Some("v1.0"));
+        assert_eq!(binaries[0].size, 4);
+        assert_eq!(binaries[0].last_used, Some(100));
+        assert!(verify_binaries(&root)
+            .unwrap()
+            .iter()
+            .all(|(_, integrity)| *integrity == Integrity::Valid));
+
+        // The least recently used release goes first
+        assert_eq!(prune_releases(&root, 2).unwrap(), vec!["v1.0"]);
+        assert_eq!(list_releases(&root).unwrap(), vec!["v1.1", "v2.0"]);
+    }
+
+    #[test]
+    fn test_cache_lock_is_exclusive() {
+        let root = TempDir::new("lock");
+        let acquired = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let lock = CacheLock::acquire(&root).unwrap();
+        let waiter = {
+            let root = root.to_path_buf();
+            let acquired = acquired.clone();
+            std::thread::spawn(move || {
+                let _lock = CacheLock::acquire(&root).unwrap();
+                acquired.store(true, std::sync::atomic::Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!acquired.load(std::sync::atomic::Ordering::SeqCst));
+
+        drop(lock);
+        waiter.join().unwrap();
+        assert!(acquired.load(std::sync::atomic::Ordering::SeqCst));
+
+        // Atomic writes leave no temporary files behind
+        write_atomic(&root.join("file"), b"contents").unwrap();
+        assert_eq!(std::fs::read(root.join("file")).unwrap(), b"contents");
+        let mut names: Vec<_> = std::fs::read_dir(&root)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![".lock", "file"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_memory_source() {
+        let root = TempDir::new("memory-source");
+        let source = MemorySource::new()
+            .with_file("bin/python3", b"python".to_vec())
+            .with_file("lib/python3.12/os.py", b"import sys".to_vec());
+
+        let path = fetch_binary(&root, &source, "python3", None).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"python");
+        assert_eq!(
+            std::fs::read(root.join("lib/python3.12/os.py")).unwrap(),
+            b"import sys"
+        );
+        assert_eq!(
+            missing_artifacts(&root, WorkloadType::Python),
+            vec!["bin/kernel.elf"]
+        );
+        assert!(fetch_binary(&root, &source, "qjs", None).await.is_err());
+
+        // A re-fetch replaces the whole sysroot
+        std::fs::write(root.join("lib/python3.12/stale.py"), b"stale").unwrap();
+        std::fs::write(&path, b"corrupt").unwrap();
+        let corrupt = sha256_file(&path).unwrap();
+        refetch_binary(&root, &source, "python3", &corrupt, None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"python");
+        assert!(root.join("lib/python3.12/os.py").is_file());
+        assert!(!root.join("lib/python3.12/stale.py").exists());
+        let names: Vec<_> = std::fs::read_dir(root.join("lib"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["python3.12"]);
+    }
+}
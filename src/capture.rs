@@ -0,0 +1,166 @@
+//! Capture of a guest's output and of the syscalls middleware denied.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::middleware::{self, Next, Syscall, SyscallMiddleware, SyscallResult};
+
+/// A syscall a middleware layer denied before it reached the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Name of the syscall, e.g. `openat`
+    pub syscall: String,
+    /// Path argument of the syscall, if it has one
+    pub path: Option<String>,
+    /// Errno the syscall failed with
+    pub errno: i32,
+}
+
+/// Bytes of stdout, and separately of stderr, kept when a run's output is captured
+pub const MAX_CAPTURED_OUTPUT: usize = 16 * 1024 * 1024;
+
+/// Middleware keeping the guest's stdout and stderr instead of printing them.
+///
+/// Each stream keeps at most `limit` bytes; later output is dropped.
+pub(crate) struct OutputCapture {
+    stdout: Mutex<Vec<u8>>,
+    stderr: Mutex<Vec<u8>>,
+    limit: usize,
+    truncated: AtomicBool,
+}
+
+impl OutputCapture {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            stdout: Mutex::default(),
+            stderr: Mutex::default(),
+            limit,
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    /// Output captured so far, as (stdout, stderr)
+    pub(crate) fn take(&self) -> (Vec<u8>, Vec<u8>) {
+        let take = |output: &Mutex<Vec<u8>>| {
+            output
+                .lock()
+                .map(|mut output| std::mem::take(&mut *output))
+                .unwrap_or_default()
+        };
+        (take(&self.stdout), take(&self.stderr))
+    }
+
+    /// Whether output beyond the limit was dropped
+    pub(crate) fn truncated(&self) -> bool {
+        self.truncated.load(Ordering::Relaxed)
+    }
+}
+
+impl SyscallMiddleware for OutputCapture {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let output = match call {
+            Syscall::Write { fd: 1, .. } => &self.stdout,
+            Syscall::Write { fd: 2, .. } => &self.stderr,
+            _ => return next.run(call),
+        };
+        let Syscall::Write { buf, .. } = call else {
+            unreachable!()
+        };
+        let mut output = output.lock().map_err(|_| libc::EIO)?;
+        let kept = buf.len().min(self.limit.saturating_sub(output.len()));
+        if kept < buf.len() {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+        output.extend_from_slice(&buf[..kept]);
+        // The guest sees its whole write succeed
+        Ok(buf.len() as i64)
+    }
+}
+
+/// Middleware recording the permission errors layers return without calling the host
+#[derive(Default)]
+pub(crate) struct ViolationTracker {
+    violations: Mutex<Vec<Violation>>,
+}
+
+impl ViolationTracker {
+    /// Violations recorded so far
+    pub(crate) fn take(&self) -> Vec<Violation> {
+        self.violations
+            .lock()
+            .map(|mut violations| std::mem::take(&mut *violations))
+            .unwrap_or_default()
+    }
+}
+
+impl SyscallMiddleware for ViolationTracker {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let (result, reached_host) = middleware::run_to_host(next, call);
+        if let Err(errno @ (libc::EPERM | libc::EACCES)) = result {
+            if !reached_host {
+                if let Ok(mut violations) = self.violations.lock() {
+                    violations.push(Violation {
+                        syscall: call.name().to_string(),
+                        path: call.path().map(|path| path.to_string_lossy().into_owned()),
+                        errno,
+                    });
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TracingLayer;
+    use std::ffi::CString;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_capture_output_and_violations() {
+        let tracker = Arc::new(ViolationTracker::default());
+        let output = Arc::new(OutputCapture::new(8));
+        let policy = Arc::new(TracingLayer {
+            name: "policy",
+            trace: Arc::new(Mutex::new(Vec::new())),
+            errno: Some(libc::EPERM),
+        });
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![tracker.clone(), output.clone()];
+
+        let mut write = Syscall::Write {
+            fd: 2,
+            buf: b"oops\n",
+        };
+        assert_eq!(Next::new(&layers).run(&mut write), Ok(5));
+        assert_eq!(output.take(), (Vec::new(), b"oops\n".to_vec()));
+
+        // Output beyond the limit is dropped, though the write succeeds
+        let mut write = Syscall::Write {
+            fd: 1,
+            buf: b"0123456789",
+        };
+        assert_eq!(Next::new(&layers).run(&mut write), Ok(10));
+        assert_eq!(output.take(), (b"01234567".to_vec(), Vec::new()));
+        assert!(output.truncated());
+
+        // Errors from the host are not violations, denials by a layer are
+        let mut unlink = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix").unwrap(),
+        };
+        assert!(Next::new(&layers).run(&mut unlink).is_err());
+        assert!(tracker.take().is_empty());
+
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![tracker.clone(), policy];
+        assert_eq!(Next::new(&layers).run(&mut unlink), Err(libc::EPERM));
+        assert_eq!(
+            tracker.take(),
+            vec![Violation {
+                syscall: "unlink".to_string(),
+                path: Some("/nonexistent/hyperlight-nanvix".to_string()),
+                errno: libc::EPERM,
+            }]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Configuration files for [`RuntimeConfig`].
+//!
+//! A `hyperlight-nanvix.toml` in the current directory or one of its parents
+//! configures the project; otherwise the user-level file in
+//! `$XDG_CONFIG_HOME` (or `~/.config`) is used. Environment variables override
+//! the file, and command-line flags override both.
+//!
+//! ```toml
+//! log_directory = "logs"
+//! tmp_directory = "/tmp/my-project"
+//! cache_directory = "/var/cache/nanvix-registry"
+//! nanvix_release = "v1.2"
+//! ```
+//!
+//! Relative directories are resolved against the directory of the file. Only
+//! settings the runtime has can be configured: the guest's memory size and
+//! environment cannot be set with the pinned Nanvix revision.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::cache;
+use crate::runtime::RuntimeConfig;
+
+/// Name of the configuration file
+pub const CONFIG_FILE_NAME: &str = "hyperlight-nanvix.toml";
+
+/// Environment variable naming the configuration file to use
+pub const CONFIG_FILE_ENV: &str = "HYPERLIGHT_NANVIX_CONFIG";
+
+/// Environment variable overriding the log directory
+pub const LOG_DIRECTORY_ENV: &str = "HYPERLIGHT_NANVIX_LOG_DIR";
+
+/// Environment variable overriding the tmp directory
+pub const TMP_DIRECTORY_ENV: &str = "HYPERLIGHT_NANVIX_TMP_DIR";
+
+/// Environment variable overriding the pinned Nanvix release
+pub const RELEASE_ENV: &str = "HYPERLIGHT_NANVIX_RELEASE";
+
+/// Contents of a configuration file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    log_directory: Option<PathBuf>,
+    tmp_directory: Option<PathBuf>,
+    cache_directory: Option<PathBuf>,
+    offline_directory: Option<PathBuf>,
+    nanvix_release: Option<String>,
+}
+
+/// The configuration file in effect: `$HYPERLIGHT_NANVIX_CONFIG`, the nearest
+/// project file, or the user-level file
+pub fn find_config_file() -> Option<PathBuf> {
+    let env_dir = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty());
+
+    if let Some(path) = env_dir(CONFIG_FILE_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let current_dir = std::env::current_dir().ok()?;
+    if let Some(path) = current_dir
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+    {
+        return Some(path);
+    }
+    let config_dir = match env_dir("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&env_dir("HOME")?).join(".config"),
+    };
+    Some(config_dir.join(CONFIG_FILE_NAME)).filter(|path| path.is_file())
+}
+
+impl RuntimeConfig {
+    /// Load the configuration file at `path`, then apply the environment overrides
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load(path.as_ref(), &process_env)
+    }
+
+    /// Load the configuration file at `path`, then apply the overrides `env` returns
+    fn load(path: &Path, env: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        // The underlying error is part of the message, as the CLI prints only the outermost one
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read configuration file {:?}: {}", path, e))?;
+        let file: ConfigFile = toml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("Invalid configuration file {:?}: {}", path, e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let resolve = |dir: PathBuf| base.join(dir).to_string_lossy().into_owned();
+
+        let mut config = RuntimeConfig::new();
+        if let Some(dir) = file.log_directory {
+            config = config.with_log_directory(resolve(dir));
+        }
+        if let Some(dir) = file.tmp_directory {
+            config = config.with_tmp_directory(resolve(dir));
+        }
+        if let Some(dir) = file.cache_directory {
+            config = config.with_cache_directory(resolve(dir));
+        }
+        if let Some(dir) = file.offline_directory {
+            config = config.with_offline_directory(resolve(dir));
+        }
+        if let Some(release) = file.nanvix_release {
+            config = config.with_nanvix_release(release);
+        }
+        Ok(config.with_env_overrides(env))
+    }
+
+    /// Load the configuration file found by [`find_config_file`], or the
+    /// defaults when there is none, then apply the environment overrides
+    pub fn discover() -> Result<Self> {
+        match find_config_file() {
+            Some(path) => Self::from_file(path),
+            None => Ok(Self::new().with_env_overrides(&process_env)),
+        }
+    }
+
+    /// Apply the settings `env` returns for the environment variables
+    fn with_env_overrides(mut self, env: &dyn Fn(&str) -> Option<String>) -> Self {
+        let env_var = |name: &str| env(name).filter(|value| !value.is_empty());
+
+        if let Some(dir) = env_var(LOG_DIRECTORY_ENV) {
+            self = self.with_log_directory(dir);
+        }
+        if let Some(dir) = env_var(TMP_DIRECTORY_ENV) {
+            self = self.with_tmp_directory(dir);
+        }
+        if let Some(dir) = env_var(cache::CACHE_DIRECTORY_ENV) {
+            self = self.with_cache_directory(dir);
+        }
+        if let Some(release) = env_var(RELEASE_ENV) {
+            self = self.with_nanvix_release(release);
+        }
+        self
+    }
+}
+
+/// Look up an environment variable of the process
+fn process_env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_config_file_with_env_overrides() {
+        let root = TempDir::new("config");
+        let path = root.join(CONFIG_FILE_NAME);
+        std::fs::write(
+            &path,
+            r#"
+                log_directory = "logs"
+                tmp_directory = "/tmp/config-test"
+                nanvix_release = "v1.2"
+            "#,
+        )
+        .unwrap();
+
+        let env = |name: &str| (name == RELEASE_ENV).then(|| "v2.0".to_string());
+        let config = RuntimeConfig::load(&path, &env).unwrap();
+
+        assert_eq!(config.log_directory, root.join("logs").to_string_lossy());
+        assert_eq!(config.tmp_directory, "/tmp/config-test");
+        // The environment takes precedence over the file
+        assert_eq!(config.nanvix_release.as_deref(), Some("v2.0"));
+
+        // Settings the runtime does not have are rejected
+        for setting in ["memory = 512", "timeout = 30", "[env]\nFOO = \"bar\""] {
+            std::fs::write(&path, setting).unwrap();
+            assert!(RuntimeConfig::from_file(&path).is_err());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Host terminal attached to an interactive guest.
+//!
+//! The [`ConsoleLayer`] serves the guest's reads of standard input from a
+//! host-side [`LineReader`], so lines are edited on the host and sent to the
+//! guest interpreter once complete. Interpreters print their prompt without a
+//! trailing newline right before reading; the layer holds such partial lines
+//! back and hands them to the reader as its prompt, so they are drawn once.
+
+use std::sync::Mutex;
+
+use crate::middleware::{Next, Syscall, SyscallMiddleware, SyscallResult};
+
+/// Source of the lines typed into an interactive guest
+pub trait LineReader: Send {
+    /// Read a line after showing `prompt`, or `None` at end of input.
+    ///
+    /// Called from the guest's `read` syscall with the console state locked, while
+    /// the run holds the process-wide middleware lock: until it returns, the guest
+    /// is stalled and no other run using middleware can start in this process.
+    fn read_line(&mut self, prompt: &str) -> Option<String>;
+}
+
+/// Middleware connecting a guest's standard streams to a [`LineReader`]
+pub struct ConsoleLayer {
+    state: Mutex<ConsoleState>,
+}
+
+struct ConsoleState {
+    reader: Box<dyn LineReader>,
+    /// Bytes of the current line not yet read by the guest
+    input: Vec<u8>,
+    /// Trailing partial line held back from stdout or stderr, and its fd
+    pending: Option<(i32, Vec<u8>)>,
+    eof: bool,
+}
+
+impl ConsoleLayer {
+    pub fn new(reader: Box<dyn LineReader>) -> Self {
+        Self {
+            state: Mutex::new(ConsoleState {
+                reader,
+                input: Vec::new(),
+                pending: None,
+                eof: false,
+            }),
+        }
+    }
+
+    /// Write out any output still held back, e.g. once the guest has exited
+    pub fn flush(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some((fd, bytes)) = state.pending.take() {
+                let _ = write_all(fd, &bytes, Next::new(&[]));
+            }
+        }
+    }
+}
+
+impl SyscallMiddleware for ConsoleLayer {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let Ok(mut state) = self.state.lock() else {
+            return next.run(call);
+        };
+        match call {
+            Syscall::Read { fd: 0, buf } => {
+                if state.input.is_empty() && !state.eof {
+                    let prompt = state
+                        .pending
+                        .take()
+                        .map(|(_, bytes)| String::from_utf8_lossy(&bytes).into_owned())
+                        .unwrap_or_default();
+                    match state.reader.read_line(&prompt) {
+                        Some(line) => {
+                            state.input.extend_from_slice(line.as_bytes());
+                            state.input.push(b'\n');
+                        }
+                        None => state.eof = true,
+                    }
+                }
+                let len = buf.len().min(state.input.len());
+                buf[..len].copy_from_slice(&state.input[..len]);
+                state.input.drain(..len);
+                Ok(len as i64)
+            }
+            Syscall::Write { fd, buf } if *fd == 1 || *fd == 2 => {
+                let layers = next.layers();
+                let mut bytes = match state.pending.take() {
+                    Some((pending_fd, pending)) if pending_fd == *fd => pending,
+                    Some((pending_fd, pending)) => {
+                        write_all(pending_fd, &pending, Next::new(layers))?;
+                        Vec::new()
+                    }
+                    None => Vec::new(),
+                };
+                bytes.extend_from_slice(buf);
+
+                // Hold back whatever follows the last newline
+                let split = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+                let partial = bytes.split_off(split);
+                if !partial.is_empty() {
+                    state.pending = Some((*fd, partial));
+                }
+                write_all(*fd, &bytes, Next::new(layers))?;
+                Ok(buf.len() as i64)
+            }
+            _ => next.run(call),
+        }
+    }
+}
+
+/// Write all of `bytes` to `fd` through the rest of the chain
+fn write_all(fd: i32, mut bytes: &[u8], next: Next<'_>) -> Result<(), i32> {
+    let layers = next.layers();
+    while !bytes.is_empty() {
+        let written = Next::new(layers).run(&mut Syscall::Write { fd, buf: bytes })?;
+        if written <= 0 {
+            return Err(libc::EIO);
+        }
+        bytes = &bytes[(written as usize).min(bytes.len())..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_console_serves_stdin_and_redraws_prompt() {
+        struct Lines(Vec<&'static str>, Arc<Mutex<Vec<String>>>);
+
+        impl LineReader for Lines {
+            fn read_line(&mut self, prompt: &str) -> Option<String> {
+                self.1.lock().unwrap().push(prompt.to_string());
+                (!self.0.is_empty()).then(|| self.0.remove(0).to_string())
+            }
+        }
+
+        struct Stdout(Arc<Mutex<Vec<u8>>>);
+
+        impl SyscallMiddleware for Stdout {
+            fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+                match call {
+                    Syscall::Write { buf, .. } => {
+                        self.0.lock().unwrap().extend_from_slice(buf);
+                        Ok(buf.len() as i64)
+                    }
+                    _ => next.run(call),
+                }
+            }
+        }
+
+        let prompts = Arc::new(Mutex::new(Vec::new()));
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let console = Arc::new(ConsoleLayer::new(Box::new(Lines(
+            vec!["1+1"],
+            prompts.clone(),
+        ))));
+        let layers: Vec<Arc<dyn SyscallMiddleware>> =
+            vec![console.clone(), Arc::new(Stdout(output.clone()))];
+
+        let write = |bytes: &[u8]| {
+            let mut call = Syscall::Write { fd: 1, buf: bytes };
+            assert_eq!(Next::new(&layers).run(&mut call), Ok(bytes.len() as i64));
+        };
+        write(b"banner\nqjs > ");
+        let mut buf = [0u8; 2];
+        let read = |buf: &mut [u8]| {
+            let mut call = Syscall::Read { fd: 0, buf };
+            Next::new(&layers).run(&mut call).unwrap()
+        };
+
+        // The prompt is held back and drawn by the line reader
+        assert_eq!(read(&mut buf), 2);
+        assert_eq!(&buf, b"1+");
+        assert_eq!(read(&mut buf), 2);
+        assert_eq!(&buf, b"1\n");
+        assert_eq!(*output.lock().unwrap(), b"banner\n");
+        assert_eq!(*prompts.lock().unwrap(), vec!["qjs > "]);
+
+        // End of input reads as end of file
+        assert_eq!(read(&mut buf), 0);
+        assert_eq!(read(&mut buf), 0);
+        assert_eq!(prompts.lock().unwrap().len(), 2);
+    }
+}
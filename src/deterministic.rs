@@ -0,0 +1,223 @@
+//! Deterministic execution: a virtual clock and seeded randomness.
+//!
+//! Guests that read the clock or draw random bytes behave differently on every
+//! run. In deterministic mode the clock starts at a fixed epoch and advances by
+//! a fixed step on every read, and entropy sources (`getrandom` and reads from
+//! `/dev/urandom` or `/dev/random`) are served from a seeded generator.
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::middleware::{Next, Syscall, SyscallMiddleware, SyscallResult};
+
+/// Amount the virtual clock advances on every read, in nanoseconds
+const CLOCK_STEP_NS: u64 = 1_000_000;
+
+/// Paths whose reads are served from the seeded generator
+const ENTROPY_DEVICES: &[&str] = &["/dev/urandom", "/dev/random"];
+
+/// Configuration of deterministic execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deterministic {
+    /// Wall-clock time the guest starts at, in seconds since the Unix epoch
+    pub epoch: u64,
+    /// Seed of the random bytes the guest receives
+    pub seed: u64,
+}
+
+/// Middleware virtualizing clocks and entropy sources
+pub struct DeterministicLayer {
+    epoch: u64,
+    state: Mutex<DeterministicState>,
+}
+
+struct DeterministicState {
+    /// Nanoseconds elapsed on the virtual clock
+    elapsed_ns: u64,
+    rng: SplitMix64,
+    /// Guest file descriptors open on an entropy device
+    entropy_fds: HashSet<i32>,
+}
+
+impl DeterministicLayer {
+    pub fn new(config: Deterministic) -> Self {
+        Self {
+            epoch: config.epoch,
+            state: Mutex::new(DeterministicState {
+                elapsed_ns: 0,
+                rng: SplitMix64(config.seed),
+                entropy_fds: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Advance the virtual clock and return the time of `clock` in nanoseconds
+    fn tick(&self, clock: i32) -> Result<u64, i32> {
+        let mut state = self.state.lock().map_err(|_| libc::EIO)?;
+        state.elapsed_ns = state.elapsed_ns.saturating_add(CLOCK_STEP_NS);
+        let realtime = matches!(
+            clock,
+            libc::CLOCK_REALTIME | libc::CLOCK_REALTIME_COARSE | libc::CLOCK_TAI
+        );
+        if realtime {
+            // An epoch too far in the future cannot be represented in nanoseconds
+            self.epoch
+                .checked_mul(1_000_000_000)
+                .and_then(|epoch_ns| epoch_ns.checked_add(state.elapsed_ns))
+                .ok_or(libc::EOVERFLOW)
+        } else {
+            Ok(state.elapsed_ns)
+        }
+    }
+
+    fn fill_random(&self, buf: &mut [u8]) -> SyscallResult {
+        let mut state = self.state.lock().map_err(|_| libc::EIO)?;
+        state.rng.fill(buf);
+        Ok(buf.len() as i64)
+    }
+}
+
+impl SyscallMiddleware for DeterministicLayer {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        match call {
+            Syscall::ClockGettime { clock, tp } => {
+                let now = self.tick(*clock)?;
+                tp.tv_sec = (now / 1_000_000_000) as libc::time_t;
+                tp.tv_nsec = (now % 1_000_000_000) as libc::c_long;
+                Ok(0)
+            }
+            Syscall::Gettimeofday { tv } => {
+                let now = self.tick(libc::CLOCK_REALTIME)?;
+                tv.tv_sec = (now / 1_000_000_000) as libc::time_t;
+                tv.tv_usec = ((now % 1_000_000_000) / 1_000) as libc::suseconds_t;
+                Ok(0)
+            }
+            Syscall::Getrandom { buf, .. } => self.fill_random(buf),
+            Syscall::Read { fd, buf } => {
+                let is_entropy = self
+                    .state
+                    .lock()
+                    .map(|state| state.entropy_fds.contains(fd))
+                    .unwrap_or(false);
+                if is_entropy {
+                    self.fill_random(buf)
+                } else {
+                    next.run(call)
+                }
+            }
+            Syscall::Openat { path, .. } => {
+                let is_entropy = ENTROPY_DEVICES
+                    .iter()
+                    .any(|device| path.as_bytes() == device.as_bytes());
+                let result = next.run(call);
+                if let (true, Ok(fd)) = (is_entropy, result) {
+                    if let Ok(mut state) = self.state.lock() {
+                        state.entropy_fds.insert(fd as i32);
+                    }
+                }
+                result
+            }
+            Syscall::Close { fd } => {
+                if let Ok(mut state) = self.state.lock() {
+                    state.entropy_fds.remove(fd);
+                }
+                next.run(call)
+            }
+            _ => next.run(call),
+        }
+    }
+}
+
+/// SplitMix64 generator: small, fast and fully determined by its seed
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Read the realtime clock and draw 16 random bytes through a deterministic layer
+    fn deterministic_sample(seed: u64) -> (libc::timespec, Vec<u8>) {
+        let layers: Vec<Arc<dyn SyscallMiddleware>> =
+            vec![Arc::new(DeterministicLayer::new(Deterministic {
+                epoch: 1_700_000_000,
+                seed,
+            }))];
+
+        let mut tp = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        Next::new(&layers)
+            .run(&mut Syscall::ClockGettime {
+                clock: libc::CLOCK_REALTIME,
+                tp: &mut tp,
+            })
+            .unwrap();
+
+        let mut buf = [0u8; 16];
+        Next::new(&layers)
+            .run(&mut Syscall::Getrandom {
+                buf: &mut buf,
+                flags: 0,
+            })
+            .unwrap();
+        (tp, buf.to_vec())
+    }
+
+    #[test]
+    fn test_deterministic_clock_and_randomness() {
+        let (tp, random) = deterministic_sample(42);
+        assert_eq!(tp.tv_sec, 1_700_000_000);
+        assert_eq!(tp.tv_nsec, 1_000_000);
+
+        let (other_tp, same_seed) = deterministic_sample(42);
+        assert_eq!(other_tp.tv_nsec, tp.tv_nsec);
+        assert_eq!(same_seed, random);
+
+        let (_, other_seed) = deterministic_sample(7);
+        assert_ne!(other_seed, random);
+    }
+
+    #[test]
+    fn test_deterministic_clock_overflow() {
+        let layers: Vec<Arc<dyn SyscallMiddleware>> =
+            vec![Arc::new(DeterministicLayer::new(Deterministic {
+                epoch: u64::MAX / 1_000,
+                seed: 0,
+            }))];
+        let mut tp = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let mut call = Syscall::ClockGettime {
+            clock: libc::CLOCK_REALTIME,
+            tp: &mut tp,
+        };
+        assert_eq!(Next::new(&layers).run(&mut call), Err(libc::EOVERFLOW));
+
+        // The monotonic clock does not depend on the epoch
+        let mut call = Syscall::ClockGettime {
+            clock: libc::CLOCK_MONOTONIC,
+            tp: &mut tp,
+        };
+        assert_eq!(Next::new(&layers).run(&mut call), Ok(0));
+    }
+}
@@ -0,0 +1,152 @@
+//! Checks of the host prerequisites for running sandboxes.
+
+use std::path::Path;
+
+use crate::cache::CachedBinary;
+
+/// Nanvix revision this crate is built against
+pub const NANVIX_REVISION: &str = env!("NANVIX_REVISION");
+
+/// Device sandboxes are run on
+pub const KVM_DEVICE: &str = "/dev/kvm";
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    /// Not a problem yet, e.g. a binary that will be downloaded on first use
+    Warn,
+    Fail,
+}
+
+/// A prerequisite that was checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    /// What was found
+    pub detail: String,
+    /// How to fix a warning or failure
+    pub fix: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn pass<S: Into<String>>(name: &str, detail: S) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    pub(crate) fn problem<S: Into<String>, F: Into<String>>(
+        name: &str,
+        status: CheckStatus,
+        detail: S,
+        fix: F,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Report of [`Runtime::diagnose`](crate::runtime::Runtime::diagnose)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    /// Nanvix revision this crate is built against
+    pub nanvix_revision: String,
+    pub checks: Vec<Check>,
+    /// Binaries in the cache, or in the offline directory
+    pub artifacts: Vec<CachedBinary>,
+}
+
+impl Diagnosis {
+    /// Whether no check failed
+    pub fn is_healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != CheckStatus::Fail)
+    }
+}
+
+/// Check that `/dev/kvm` exists and can be opened for reading and writing
+pub(crate) fn check_kvm(device: &Path) -> Vec<Check> {
+    if !device.exists() {
+        return vec![Check::problem(
+            "KVM device",
+            CheckStatus::Fail,
+            format!("{} does not exist", device.display()),
+            "Enable virtualization in the firmware and load the KVM module \
+             (`modprobe kvm_intel` or `modprobe kvm_amd`)",
+        )];
+    }
+    let access = match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(device)
+    {
+        Ok(_) => Check::pass("KVM access", format!("{} is accessible", device.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Check::problem(
+            "KVM access",
+            CheckStatus::Fail,
+            format!("{}: {}", device.display(), e),
+            "Add your user to the kvm group (`sudo usermod -aG kvm $USER`) and log in again",
+        ),
+        Err(e) => Check::problem(
+            "KVM access",
+            CheckStatus::Fail,
+            format!("{}: {}", device.display(), e),
+            "Check that KVM works on this host, e.g. with `kvm-ok`",
+        ),
+    };
+    vec![
+        Check::pass("KVM device", format!("{} exists", device.display())),
+        access,
+    ]
+}
+
+/// Check that files can be created in `dir`, or that `dir` itself can be created.
+///
+/// Nothing is written: the directory, or its nearest existing ancestor, is
+/// checked for write access.
+pub(crate) fn check_writable(name: &str, dir: &Path, fix: &str) -> Check {
+    let existing = dir
+        .ancestors()
+        .map(|path| match path.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => path,
+        })
+        .find(|path| path.exists())
+        .unwrap_or(Path::new("/"));
+    let result = if !existing.is_dir() {
+        Err(format!("{} is not a directory", existing.display()))
+    } else {
+        can_write(existing).map_err(|e| format!("{}: {}", existing.display(), e))
+    };
+    match result {
+        Ok(()) if existing == dir => Check::pass(name, format!("{} is writable", dir.display())),
+        Ok(()) => Check::pass(
+            name,
+            format!("{} can be created in {}", dir.display(), existing.display()),
+        ),
+        Err(detail) => Check::problem(name, CheckStatus::Fail, detail, fix),
+    }
+}
+
+/// Whether this process may create entries in the directory `dir`
+fn can_write(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mode = libc::W_OK | libc::X_OK;
+    match unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), mode, libc::AT_EACCESS) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
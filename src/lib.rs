@@ -1,8 +1,19 @@
 use anyhow::Result;
 use std::path::Path;
 
+pub mod artifacts;
+pub mod bundle;
 pub mod cache;
+pub mod capture;
+pub mod config;
+pub mod console;
+pub mod deterministic;
+pub mod diagnose;
+pub mod middleware;
+pub mod overlay;
+pub mod replay;
 pub mod runtime;
+pub mod source;
 
 #[cfg(feature = "napi")]
 pub mod napi;
@@ -10,11 +21,27 @@ pub mod napi;
 #[cfg(feature = "python")]
 pub mod python;
 
+#[cfg(test)]
+mod test_util;
+
 #[cfg(test)]
 mod unit_tests;
 
+pub use artifacts::{Artifact, ArtifactsConfig};
+pub use cache::Verification;
+pub use capture::{Violation, MAX_CAPTURED_OUTPUT};
+pub use console::LineReader;
+pub use deterministic::Deterministic;
+pub use diagnose::{Check, CheckStatus, Diagnosis};
+pub use middleware::{Next, Syscall, SyscallMiddleware, SyscallResult};
+pub use overlay::OverlayDiff;
+pub use replay::{Divergence, Recorder, Replayer, SyscallRecord};
 use runtime::Runtime;
-pub use runtime::{RuntimeConfig, WorkloadType};
+pub use runtime::{BinaryVersion, RunFailure, RunResult, RuntimeConfig, WorkloadType};
+pub use source::{
+    ArtifactSource, DirectorySource, DownloadProgress, MemorySource, MirrorSource,
+    ProgressCallback, RegistrySource,
+};
 
 // Re-export Nanvix sandbox types for syscall table configuration
 pub use nanvix::sandbox::{SyscallAction, SyscallTable};
@@ -32,10 +59,43 @@ impl Sandbox {
     }
 
     /// Run a workload in the sandbox
-    pub async fn run<P: AsRef<Path>>(&mut self, workload_path: P) -> Result<()> {
+    pub async fn run<P: AsRef<Path>>(&mut self, workload_path: P) -> Result<RunResult> {
         self.runtime.run(workload_path).await
     }
 
+    /// Run a workload, passing `guest_args` to the guest program
+    pub async fn run_with_args<P: AsRef<Path>>(
+        &mut self,
+        workload_path: P,
+        guest_args: &[String],
+    ) -> Result<RunResult> {
+        self.runtime.run_with_args(workload_path, guest_args).await
+    }
+
+    /// Run `code` as a JavaScript or Python script, passing `guest_args` to it
+    pub async fn run_code(
+        &mut self,
+        workload_type: WorkloadType,
+        code: &str,
+        guest_args: &[String],
+    ) -> Result<RunResult> {
+        self.runtime.run_code(workload_type, code, guest_args).await
+    }
+
+    /// Start the interactive interpreter of `workload_type`, reading its input from `reader`
+    pub async fn repl(
+        &mut self,
+        workload_type: WorkloadType,
+        reader: Box<dyn LineReader>,
+    ) -> Result<RunResult> {
+        self.runtime.repl(workload_type, reader).await
+    }
+
+    /// Check the host prerequisites for running this sandbox
+    pub fn diagnose(&self) -> Diagnosis {
+        self.runtime.diagnose()
+    }
+
     /// Clear the binary cache to force fresh downloads on next run
     pub async fn clear_cache(&self) -> Result<()> {
         self.runtime.clear_cache().await
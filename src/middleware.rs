@@ -0,0 +1,448 @@
+//! Composable syscall middleware.
+//!
+//! A [`SyscallTable`] holds a single action per syscall. Middleware lets several
+//! independent layers (tracers, policies, quotas, ...) hook the same syscall: each
+//! layer inspects the call, may rewrite its arguments, short-circuits with an
+//! errno or passes it on to the next layer. The chain ends at the default
+//! forwarder, which performs the call on the host.
+
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex};
+
+use crate::{SyscallAction, SyscallTable};
+
+/// Result of a syscall: the return value on success or an errno on failure
+pub type SyscallResult = Result<i64, i32>;
+
+/// Middleware layers of a chain, outermost first
+type Layers = Arc<[Arc<dyn SyscallMiddleware>]>;
+
+/// An intercepted syscall and its arguments
+#[derive(Debug)]
+pub enum Syscall<'a> {
+    Openat {
+        dirfd: i32,
+        path: CString,
+        flags: i32,
+        mode: u32,
+    },
+    Unlink {
+        path: CString,
+    },
+    Mkdir {
+        path: CString,
+        mode: u32,
+    },
+    Read {
+        fd: i32,
+        buf: &'a mut [u8],
+    },
+    Write {
+        fd: i32,
+        buf: &'a [u8],
+    },
+    Close {
+        fd: i32,
+    },
+    ClockGettime {
+        clock: i32,
+        tp: &'a mut libc::timespec,
+    },
+    Gettimeofday {
+        tv: &'a mut libc::timeval,
+    },
+    Getrandom {
+        buf: &'a mut [u8],
+        flags: u32,
+    },
+}
+
+impl Syscall<'_> {
+    /// Name of the syscall
+    pub fn name(&self) -> &'static str {
+        match self {
+            Syscall::Openat { .. } => "openat",
+            Syscall::Unlink { .. } => "unlink",
+            Syscall::Mkdir { .. } => "mkdir",
+            Syscall::Read { .. } => "read",
+            Syscall::Write { .. } => "write",
+            Syscall::Close { .. } => "close",
+            Syscall::ClockGettime { .. } => "clock_gettime",
+            Syscall::Gettimeofday { .. } => "gettimeofday",
+            Syscall::Getrandom { .. } => "getrandom",
+        }
+    }
+
+    /// Path argument of the syscall, if it has one
+    pub fn path(&self) -> Option<&CStr> {
+        match self {
+            Syscall::Openat { path, .. }
+            | Syscall::Unlink { path }
+            | Syscall::Mkdir { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Directory descriptor a relative path argument is resolved against
+    pub fn dirfd(&self) -> i32 {
+        match self {
+            Syscall::Openat { dirfd, .. } => *dirfd,
+            _ => libc::AT_FDCWD,
+        }
+    }
+
+    /// Memory the syscall writes its results to, if any
+    pub fn output_mut(&mut self) -> Option<&mut [u8]> {
+        match self {
+            Syscall::Read { buf, .. } | Syscall::Getrandom { buf, .. } => Some(buf),
+            Syscall::ClockGettime { tp, .. } => Some(as_bytes_mut(*tp)),
+            Syscall::Gettimeofday { tv } => Some(as_bytes_mut(*tv)),
+            _ => None,
+        }
+    }
+}
+
+/// View a plain C struct as its raw bytes
+fn as_bytes_mut<T: Copy>(value: &mut T) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut((value as *mut T).cast(), std::mem::size_of::<T>()) }
+}
+
+/// A layer of the syscall middleware chain
+///
+/// Syscall handlers are plain function pointers with no per-sandbox context,
+/// so only one chain can be installed at a time. Runs that use middleware
+/// (including recording, replay, overlays and artifact collection) are
+/// serialized process-wide: a second such run waits until the first one
+/// finishes, even across different `Sandbox` instances. Runs without
+/// middleware are not affected.
+pub trait SyscallMiddleware: Send + Sync {
+    /// Handle a syscall. Call `next.run(call)` to pass it down the chain, or
+    /// return without doing so to short-circuit it.
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult;
+}
+
+/// The remainder of a middleware chain
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn SyscallMiddleware>],
+}
+
+impl<'a> Next<'a> {
+    /// Start of a chain made of `layers`, outermost first
+    pub fn new(layers: &'a [Arc<dyn SyscallMiddleware>]) -> Self {
+        Self { layers }
+    }
+
+    /// Layers remaining in the chain, to pass several calls down it
+    pub(crate) fn layers(&self) -> &'a [Arc<dyn SyscallMiddleware>] {
+        self.layers
+    }
+
+    /// Pass the syscall to the next layer, or to the host when the chain is exhausted
+    pub fn run(self, call: &mut Syscall<'_>) -> SyscallResult {
+        match self.layers.split_first() {
+            Some((layer, rest)) => layer.handle(call, Next { layers: rest }),
+            None => {
+                REACHED_HOST.with(|reached| reached.set(true));
+                forward(call)
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// Whether the syscall passed down the chain on this thread reached the host
+    static REACHED_HOST: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Pass the syscall down the chain, returning whether it was performed on the host
+pub(crate) fn run_to_host(next: Next<'_>, call: &mut Syscall<'_>) -> (SyscallResult, bool) {
+    REACHED_HOST.with(|reached| reached.set(false));
+    let result = next.run(call);
+    (result, REACHED_HOST.with(Cell::get))
+}
+
+/// Perform a syscall on the host
+pub fn forward(call: &mut Syscall<'_>) -> SyscallResult {
+    let result = unsafe {
+        match call {
+            Syscall::Openat {
+                dirfd,
+                path,
+                flags,
+                mode,
+            } => libc::openat(*dirfd, path.as_ptr(), *flags, *mode) as i64,
+            Syscall::Unlink { path } => libc::unlink(path.as_ptr()) as i64,
+            Syscall::Mkdir { path, mode } => libc::mkdir(path.as_ptr(), *mode) as i64,
+            Syscall::Read { fd, buf } => libc::read(*fd, buf.as_mut_ptr().cast(), buf.len()) as i64,
+            Syscall::Write { fd, buf } => libc::write(*fd, buf.as_ptr().cast(), buf.len()) as i64,
+            Syscall::Close { fd } => libc::close(*fd) as i64,
+            Syscall::ClockGettime { clock, tp } => libc::clock_gettime(*clock, *tp) as i64,
+            Syscall::Gettimeofday { tv } => libc::gettimeofday(*tv, std::ptr::null_mut()) as i64,
+            Syscall::Getrandom { buf, flags } => {
+                libc::getrandom(buf.as_mut_ptr().cast(), buf.len(), *flags) as i64
+            }
+        }
+    };
+    if result < 0 {
+        Err(std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Chain consulted by the syscall handlers.
+///
+/// Syscall handlers are plain function pointers, so the chain of the runtime
+/// that is running is published here for the duration of the run.
+static ACTIVE_CHAIN: Mutex<Option<Layers>> = Mutex::new(None);
+
+/// Serializes runs that have a middleware chain installed, across all runtimes
+static CHAIN_RUN_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Keeps a middleware chain installed until dropped
+pub(crate) struct ChainGuard {
+    _lock: tokio::sync::MutexGuard<'static, ()>,
+}
+
+impl Drop for ChainGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE_CHAIN.lock() {
+            *active = None;
+        }
+    }
+}
+
+/// Install `layers` as the chain consulted by the syscall handlers
+pub(crate) async fn install(layers: Vec<Arc<dyn SyscallMiddleware>>) -> ChainGuard {
+    let lock = CHAIN_RUN_LOCK.lock().await;
+    if let Ok(mut active) = ACTIVE_CHAIN.lock() {
+        *active = Some(layers.into());
+    }
+    ChainGuard { _lock: lock }
+}
+
+/// Build a syscall table routing every supported syscall through the installed chain
+pub(crate) fn syscall_table() -> SyscallTable<()> {
+    let mut table = SyscallTable::new(());
+    table.openat = SyscallAction::Forward(openat_handler);
+    table.unlink = SyscallAction::Forward(unlink_handler);
+    table.mkdir = SyscallAction::Forward(mkdir_handler);
+    table.read = SyscallAction::Forward(read_handler);
+    table.write = SyscallAction::Forward(write_handler);
+    table.close = SyscallAction::Forward(close_handler);
+    table.clock_gettime = SyscallAction::Forward(clock_gettime_handler);
+    table.gettimeofday = SyscallAction::Forward(gettimeofday_handler);
+    table.getrandom = SyscallAction::Forward(getrandom_handler);
+    table
+}
+
+/// Fold the `.` and `..` components of an absolute guest path without touching
+/// the filesystem.
+///
+/// Returns `None` for relative paths and for paths whose `..` components climb
+/// above `/`.
+pub(crate) fn normalize_absolute(path: &str) -> Option<String> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+    Some(format!("/{}", components.join("/")))
+}
+
+/// The absolute, normalized form of a guest path given relative to `dirfd`.
+///
+/// The guest shares the working directory of the host process, so relative
+/// paths with `AT_FDCWD` are resolved against it. Returns `None` for relative
+/// paths with any other descriptor and for paths that climb above `/`.
+pub(crate) fn resolve_path(dirfd: i32, path: &str) -> Option<String> {
+    if path.starts_with('/') {
+        return normalize_absolute(path);
+    }
+    if dirfd != libc::AT_FDCWD {
+        return None;
+    }
+    let cwd = std::env::current_dir().ok()?;
+    normalize_absolute(&format!("{}/{}", cwd.to_string_lossy(), path))
+}
+
+/// Run a syscall through the installed chain and convert the result to the libc convention
+fn dispatch(call: &mut Syscall<'_>) -> i64 {
+    let chain = ACTIVE_CHAIN.lock().ok().and_then(|active| active.clone());
+    let result = match chain {
+        Some(layers) => Next::new(&layers).run(call),
+        None => forward(call),
+    };
+    match result {
+        Ok(value) => value,
+        Err(errno) => {
+            unsafe { *libc::__errno_location() = errno };
+            -1
+        }
+    }
+}
+
+unsafe fn openat_handler(
+    _state: &(),
+    dirfd: i32,
+    pathname: *const i8,
+    flags: i32,
+    mode: u32,
+) -> i32 {
+    let mut call = Syscall::Openat {
+        dirfd,
+        path: CStr::from_ptr(pathname).to_owned(),
+        flags,
+        mode,
+    };
+    dispatch(&mut call) as i32
+}
+
+unsafe fn unlink_handler(_state: &(), pathname: *const i8) -> i32 {
+    let mut call = Syscall::Unlink {
+        path: CStr::from_ptr(pathname).to_owned(),
+    };
+    dispatch(&mut call) as i32
+}
+
+unsafe fn mkdir_handler(_state: &(), pathname: *const i8, mode: u32) -> i32 {
+    let mut call = Syscall::Mkdir {
+        path: CStr::from_ptr(pathname).to_owned(),
+        mode,
+    };
+    dispatch(&mut call) as i32
+}
+
+unsafe fn read_handler(_state: &(), fd: i32, buf: *mut libc::c_void, count: usize) -> isize {
+    let buf: &mut [u8] = if buf.is_null() || count == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(buf.cast(), count)
+    };
+    dispatch(&mut Syscall::Read { fd, buf }) as isize
+}
+
+unsafe fn write_handler(_state: &(), fd: i32, buf: *const libc::c_void, count: usize) -> isize {
+    let buf: &[u8] = if buf.is_null() || count == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(buf.cast(), count)
+    };
+    dispatch(&mut Syscall::Write { fd, buf }) as isize
+}
+
+unsafe fn close_handler(_state: &(), fd: i32) -> i32 {
+    dispatch(&mut Syscall::Close { fd }) as i32
+}
+
+unsafe fn clock_gettime_handler(_state: &(), clock: i32, tp: *mut libc::timespec) -> i32 {
+    match tp.as_mut() {
+        Some(tp) => dispatch(&mut Syscall::ClockGettime { clock, tp }) as i32,
+        None => libc::clock_gettime(clock, tp),
+    }
+}
+
+unsafe fn gettimeofday_handler(_state: &(), tv: *mut libc::timeval, tz: *mut libc::c_void) -> i32 {
+    match tv.as_mut() {
+        Some(tv) => dispatch(&mut Syscall::Gettimeofday { tv }) as i32,
+        None => libc::gettimeofday(tv, tz.cast()),
+    }
+}
+
+unsafe fn getrandom_handler(
+    _state: &(),
+    buf: *mut libc::c_void,
+    buflen: usize,
+    flags: u32,
+) -> isize {
+    let buf: &mut [u8] = if buf.is_null() || buflen == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(buf.cast(), buflen)
+    };
+    dispatch(&mut Syscall::Getrandom { buf, flags }) as isize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, TracingLayer};
+
+    #[test]
+    fn test_middleware_chain_order_and_short_circuit() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![
+            Arc::new(TracingLayer {
+                name: "tracer",
+                trace: trace.clone(),
+                errno: None,
+            }),
+            Arc::new(TracingLayer {
+                name: "policy",
+                trace: trace.clone(),
+                errno: Some(libc::EPERM),
+            }),
+            Arc::new(TracingLayer {
+                name: "quota",
+                trace: trace.clone(),
+                errno: None,
+            }),
+        ];
+
+        let mut call = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix").unwrap(),
+        };
+        let result = Next::new(&layers).run(&mut call);
+
+        assert_eq!(result, Err(libc::EPERM));
+        assert_eq!(*trace.lock().unwrap(), vec!["tracer", "policy"]);
+    }
+
+    #[test]
+    fn test_middleware_rewrites_arguments() {
+        struct Redirect(CString);
+
+        impl SyscallMiddleware for Redirect {
+            fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+                if let Syscall::Unlink { path } = call {
+                    *path = self.0.clone();
+                }
+                next.run(call)
+            }
+        }
+
+        let root = TempDir::new("middleware");
+        let target = root.join("target");
+        std::fs::write(&target, b"data").unwrap();
+
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![Arc::new(Redirect(
+            CString::new(target.to_string_lossy().as_bytes()).unwrap(),
+        ))];
+        let mut call = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix").unwrap(),
+        };
+
+        assert_eq!(Next::new(&layers).run(&mut call), Ok(0));
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn test_normalize_absolute() {
+        use crate::middleware::normalize_absolute;
+        assert_eq!(normalize_absolute("/a/./b/../c/").as_deref(), Some("/a/c"));
+        assert_eq!(normalize_absolute("/a/..").as_deref(), Some("/"));
+        assert_eq!(normalize_absolute("/a/../.."), None);
+        assert_eq!(normalize_absolute("a/b"), None);
+    }
+}
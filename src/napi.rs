@@ -1,7 +1,22 @@
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
 use napi::bindgen_prelude::*;
+use napi::threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode};
 use napi_derive::napi;
 
+use crate::middleware::{Next, Syscall, SyscallMiddleware, SyscallResult};
 use crate::runtime::{Runtime, RuntimeConfig};
+use crate::source::DownloadProgress;
+
+/// Default time a syscall callback has to answer before the call is denied.
+const DEFAULT_SYSCALL_TIMEOUT_MS: u32 = 1000;
+
+/// JavaScript callback deciding whether an intercepted syscall may proceed.
+pub type SyscallCallback = ThreadsafeFunction<SyscallEvent, bool, SyscallEvent, Status, false>;
+
+/// JavaScript callback receiving the progress of binary downloads.
+pub type ProgressCallback = ThreadsafeFunction<ProgressEvent, (), ProgressEvent, Status, false>;
 
 /// Node.js wrapper for hyperlight-nanvix Runtime
 #[napi]
@@ -10,12 +25,53 @@ pub struct NanvixSandbox {
 }
 
 /// Configuration options for creating a sandbox
-#[napi(object)]
+#[napi(object, object_to_js = false)]
 pub struct SandboxConfig {
     /// Directory for storing logs
     pub log_directory: Option<String>,
     /// Directory for temporary files
     pub tmp_directory: Option<String>,
+    /// Callbacks invoked for intercepted guest syscalls
+    pub syscall_hooks: Option<SyscallHooks>,
+    /// Time in milliseconds a syscall callback has to answer before the call is denied
+    pub syscall_timeout_ms: Option<u32>,
+    /// Callback invoked with a `ProgressEvent` while binaries download
+    pub on_progress: Option<ProgressCallback>,
+}
+
+/// Per-syscall callbacks. Each callback receives a `SyscallEvent` and must
+/// synchronously return `true` to allow the call or `false` to deny it.
+#[napi(object, object_to_js = false)]
+pub struct SyscallHooks {
+    pub openat: Option<SyscallCallback>,
+    pub unlink: Option<SyscallCallback>,
+    pub mkdir: Option<SyscallCallback>,
+}
+
+/// Description of an intercepted syscall passed to JavaScript callbacks
+#[napi(object)]
+pub struct SyscallEvent {
+    /// Name of the syscall (e.g. `openat`)
+    pub syscall: String,
+    /// Path argument of the syscall
+    pub path: String,
+    /// Directory file descriptor (`openat` only)
+    pub dirfd: Option<i32>,
+    /// Open flags (`openat` only)
+    pub flags: Option<i32>,
+    /// Creation mode (`openat` and `mkdir` only)
+    pub mode: Option<u32>,
+}
+
+/// Progress of a binary download passed to JavaScript callbacks
+#[napi(object)]
+pub struct ProgressEvent {
+    /// Artifact being downloaded (e.g. `bin/qjs`)
+    pub artifact: String,
+    /// Bytes downloaded so far
+    pub downloaded: f64,
+    /// Size of the artifact, when known
+    pub total: Option<f64>,
 }
 
 /// Workload execution result
@@ -25,6 +81,83 @@ pub struct WorkloadResult {
     pub error: Option<String>,
 }
 
+/// Syscall callbacks registered by a sandbox
+struct JsSyscallHooks {
+    openat: Option<SyscallCallback>,
+    unlink: Option<SyscallCallback>,
+    mkdir: Option<SyscallCallback>,
+    timeout: Duration,
+}
+
+/// Describe a syscall for JavaScript callbacks, or `None` if it cannot be hooked.
+fn syscall_event(call: &Syscall<'_>) -> Option<SyscallEvent> {
+    let path = call.path()?.to_string_lossy().to_string();
+    let (dirfd, flags, mode) = match call {
+        Syscall::Openat {
+            dirfd, flags, mode, ..
+        } => (Some(*dirfd), Some(*flags), Some(*mode)),
+        Syscall::Mkdir { mode, .. } => (None, None, Some(*mode)),
+        _ => (None, None, None),
+    };
+    Some(SyscallEvent {
+        syscall: call.name().to_string(),
+        path,
+        dirfd,
+        flags,
+        mode,
+    })
+}
+
+/// Wait at most `timeout` for a callback's answer, denying the call if none arrives.
+fn await_decision(rx: &mpsc::Receiver<bool>, timeout: Duration) -> bool {
+    rx.recv_timeout(timeout).unwrap_or(false)
+}
+
+impl JsSyscallHooks {
+    /// Ask a JavaScript callback whether a syscall may proceed.
+    ///
+    /// The callback is queued on the JS event loop without blocking it, and the
+    /// guest thread waits at most `timeout` for the answer. A callback that
+    /// throws, returns a non-boolean or does not answer in time denies the call.
+    fn allows(&self, callback: &SyscallCallback, event: SyscallEvent) -> bool {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let status = callback.call_with_return_value(
+            event,
+            ThreadsafeFunctionCallMode::NonBlocking,
+            move |result: Result<bool>, _env| {
+                let _ = tx.send(result.unwrap_or(false));
+                Ok(())
+            },
+        );
+        if status != Status::Ok {
+            return false;
+        }
+        await_decision(&rx, self.timeout)
+    }
+
+    /// Callback registered for a syscall, if any
+    fn callback(&self, syscall: &str) -> Option<&SyscallCallback> {
+        match syscall {
+            "openat" => self.openat.as_ref(),
+            "unlink" => self.unlink.as_ref(),
+            "mkdir" => self.mkdir.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl SyscallMiddleware for JsSyscallHooks {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let callback = self.callback(call.name());
+        if let (Some(callback), Some(event)) = (callback, syscall_event(call)) {
+            if !self.allows(callback, event) {
+                return Err(libc::EACCES);
+            }
+        }
+        next.run(call)
+    }
+}
+
 #[napi]
 impl NanvixSandbox {
     /// Create a new sandbox instance
@@ -39,6 +172,28 @@ impl NanvixSandbox {
                 if let Some(tmp_dir) = cfg.tmp_directory {
                     runtime_config = runtime_config.with_tmp_directory(tmp_dir);
                 }
+                if let Some(syscall_hooks) = cfg.syscall_hooks {
+                    let timeout_ms = cfg.syscall_timeout_ms.unwrap_or(DEFAULT_SYSCALL_TIMEOUT_MS);
+                    let js_hooks = JsSyscallHooks {
+                        openat: syscall_hooks.openat,
+                        unlink: syscall_hooks.unlink,
+                        mkdir: syscall_hooks.mkdir,
+                        timeout: Duration::from_millis(timeout_ms.into()),
+                    };
+                    runtime_config = runtime_config.with_syscall_middleware(Arc::new(js_hooks));
+                }
+                if let Some(callback) = cfg.on_progress {
+                    runtime_config = runtime_config.with_progress(Arc::new(
+                        move |progress: &DownloadProgress| {
+                            let event = ProgressEvent {
+                                artifact: progress.artifact.clone(),
+                                downloaded: progress.downloaded as f64,
+                                total: progress.total.map(|total| total as f64),
+                            };
+                            callback.call(event, ThreadsafeFunctionCallMode::NonBlocking);
+                        },
+                    ));
+                }
                 runtime_config
             }
             None => RuntimeConfig::new(),
@@ -55,7 +210,7 @@ impl NanvixSandbox {
     pub async fn run(&self, workload_path: String) -> Result<WorkloadResult> {
         // Run the workload using the existing runtime
         match self.runtime.run(&workload_path).await {
-            Ok(()) => Ok(WorkloadResult {
+            Ok(_) => Ok(WorkloadResult {
                 success: true,
                 error: None,
             }),
@@ -75,3 +230,58 @@ impl NanvixSandbox {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::time::Instant;
+
+    #[test]
+    fn test_syscall_event_maps_arguments() {
+        let openat = Syscall::Openat {
+            dirfd: libc::AT_FDCWD,
+            path: CString::new("/etc/passwd").unwrap(),
+            flags: libc::O_RDONLY,
+            mode: 0,
+        };
+        let event = syscall_event(&openat).unwrap();
+        assert_eq!(event.syscall, "openat");
+        assert_eq!(event.path, "/etc/passwd");
+        assert_eq!(event.dirfd, Some(libc::AT_FDCWD));
+        assert_eq!(event.flags, Some(libc::O_RDONLY));
+        assert_eq!(event.mode, Some(0));
+
+        let unlink = Syscall::Unlink {
+            path: CString::new("/tmp/a").unwrap(),
+        };
+        let event = syscall_event(&unlink).unwrap();
+        assert_eq!(event.syscall, "unlink");
+        assert_eq!(event.path, "/tmp/a");
+        assert_eq!((event.dirfd, event.flags, event.mode), (None, None, None));
+
+        let mkdir = Syscall::Mkdir {
+            path: CString::new("/tmp/d").unwrap(),
+            mode: 0o755,
+        };
+        let event = syscall_event(&mkdir).unwrap();
+        assert_eq!(event.syscall, "mkdir");
+        assert_eq!(event.mode, Some(0o755));
+
+        assert!(syscall_event(&Syscall::Close { fd: 3 }).is_none());
+    }
+
+    #[test]
+    fn test_await_decision_denies_on_timeout() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let started = Instant::now();
+        assert!(!await_decision(&rx, Duration::from_millis(20)));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        tx.send(true).unwrap();
+        assert!(await_decision(&rx, Duration::from_millis(20)));
+
+        drop(tx);
+        assert!(!await_decision(&rx, Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,423 @@
+//! Copy-on-write overlay filesystem for guest writes.
+//!
+//! In overlay mode the host filesystem is the read-only lower layer. Files the
+//! guest creates, writes or deletes are redirected into a per-run upper
+//! directory: writes copy the file up first, deletions are recorded as
+//! whiteouts, and reads prefer the upper copy and fall through to the real
+//! path otherwise. After the run the changes are available as an
+//! [`OverlayDiff`] that can be inspected, committed to the host or discarded.
+//!
+//! Only absolute paths passed to `openat`, `unlink` and `mkdir` are overlaid.
+//! Their `.` and `..` components are folded before anything else, and paths
+//! that climb above `/` are refused. The upper directory is removed when the
+//! layer is dropped before its changes were handed out with
+//! [`OverlayLayer::diff`], e.g. because the run failed.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use nanvix::log;
+
+use crate::artifacts::collect_files;
+use crate::middleware::{
+    normalize_absolute, resolve_path, Next, Syscall, SyscallMiddleware, SyscallResult,
+};
+
+/// Open flags that modify the file being opened
+const WRITE_FLAGS: i32 =
+    libc::O_WRONLY | libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC | libc::O_APPEND;
+
+/// Changes a guest made through the overlay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OverlayDiff {
+    /// Files that did not exist on the host
+    pub added: Vec<String>,
+    /// Host files whose contents changed
+    pub modified: Vec<String>,
+    /// Host files the guest deleted
+    pub deleted: Vec<String>,
+    /// Directory holding the new contents of added and modified files
+    pub upper_directory: PathBuf,
+}
+
+impl OverlayDiff {
+    /// Whether the guest left the host filesystem unchanged
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
+
+    /// Path of the new contents of an added or modified file, or `None` if
+    /// `path` is not absolute or climbs above `/`
+    pub fn upper_path(&self, path: &str) -> Option<PathBuf> {
+        let path = normalize_absolute(path)?;
+        Some(self.upper_directory.join(path.trim_start_matches('/')))
+    }
+
+    /// Apply the changes to the host filesystem and remove the upper directory
+    pub fn commit(self) -> Result<()> {
+        for path in self.added.iter().chain(&self.modified) {
+            if let Some(parent) = Path::new(path).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let upper = self
+                .upper_path(path)
+                .with_context(|| format!("Invalid overlay path {}", path))?;
+            std::fs::copy(upper, path).with_context(|| format!("Failed to commit {}", path))?;
+        }
+        for path in &self.deleted {
+            std::fs::remove_file(path).with_context(|| format!("Failed to delete {}", path))?;
+        }
+        self.discard()
+    }
+
+    /// Drop the changes and remove the upper directory
+    pub fn discard(self) -> Result<()> {
+        if self.upper_directory.exists() {
+            std::fs::remove_dir_all(&self.upper_directory)?;
+        }
+        Ok(())
+    }
+}
+
+/// Middleware redirecting guest filesystem changes into an upper directory
+pub struct OverlayLayer {
+    upper: PathBuf,
+    /// Host directories the overlay leaves alone
+    passthrough: Vec<PathBuf>,
+    /// Host files the guest deleted
+    whiteouts: Mutex<HashSet<String>>,
+    /// Whether the upper directory was handed out in a diff and must be kept
+    handed_out: AtomicBool,
+}
+
+impl OverlayLayer {
+    /// Create an overlay storing changes in `upper`. Paths below any of the
+    /// `passthrough` directories are not overlaid.
+    pub fn new<P: Into<PathBuf>>(upper: P, passthrough: Vec<PathBuf>) -> Result<Self> {
+        let upper = upper.into();
+        std::fs::create_dir_all(&upper)
+            .with_context(|| format!("Failed to create overlay directory {:?}", upper))?;
+        let mut passthrough = passthrough;
+        passthrough.push(upper.clone());
+        let passthrough = passthrough
+            .iter()
+            .map(|dir| host_path(dir))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            upper,
+            passthrough,
+            whiteouts: Mutex::new(HashSet::new()),
+            handed_out: AtomicBool::new(false),
+        })
+    }
+
+    /// The path argument of a call resolved against the working directory and
+    /// normalized, if it is overlaid.
+    ///
+    /// Paths that climb above `/` are refused with `EACCES`, and so are calls
+    /// that modify a path the overlay cannot resolve, such as one relative to a
+    /// directory descriptor other than `AT_FDCWD`.
+    fn overlaid(&self, call: &Syscall<'_>) -> Result<Option<String>, i32> {
+        let Some(path) = call.path() else {
+            return Ok(None);
+        };
+        let Some(path) = path
+            .to_str()
+            .ok()
+            .filter(|path| path.starts_with('/') || call.dirfd() == libc::AT_FDCWD)
+        else {
+            return if modifies(call) {
+                Err(libc::EACCES)
+            } else {
+                Ok(None)
+            };
+        };
+        let path = resolve_path(call.dirfd(), path).ok_or(libc::EACCES)?;
+        let passthrough = self
+            .passthrough
+            .iter()
+            .any(|dir| Path::new(&path).starts_with(dir));
+        Ok((!passthrough).then_some(path))
+    }
+
+    /// Upper copy of a guest path already normalized by [`Self::overlaid`]
+    fn upper_path(&self, path: &str) -> PathBuf {
+        self.upper.join(path.trim_start_matches('/'))
+    }
+
+    fn is_deleted(&self, path: &str) -> bool {
+        self.whiteouts
+            .lock()
+            .map(|whiteouts| whiteouts.contains(path))
+            .unwrap_or(false)
+    }
+
+    /// Prepare the upper copy of `path` for writing
+    fn copy_up(&self, path: &str, truncate: bool) -> Result<PathBuf, i32> {
+        let upper = self.upper_path(path);
+        if let Some(parent) = upper.parent() {
+            std::fs::create_dir_all(parent).map_err(errno)?;
+        }
+        let deleted = self
+            .whiteouts
+            .lock()
+            .map(|mut whiteouts| whiteouts.remove(path))
+            .map_err(|_| libc::EIO)?;
+        if !upper.exists() && !deleted && !truncate && Path::new(path).is_file() {
+            std::fs::copy(path, &upper).map_err(errno)?;
+        }
+        Ok(upper)
+    }
+
+    /// Compute the changes the guest made.
+    ///
+    /// The upper directory then belongs to the diff and is kept until it is
+    /// committed or discarded.
+    pub fn diff(&self) -> Result<OverlayDiff> {
+        self.handed_out.store(true, Ordering::SeqCst);
+        let mut files = Vec::new();
+        collect_files(&self.upper, &mut files)?;
+        files.sort();
+
+        let mut diff = OverlayDiff {
+            upper_directory: self.upper.clone(),
+            ..OverlayDiff::default()
+        };
+        for file in files {
+            let path = format!("/{}", file.strip_prefix(&self.upper)?.to_string_lossy());
+            if !Path::new(&path).is_file() {
+                diff.added.push(path);
+            } else if std::fs::read(&path)? != std::fs::read(&file)? {
+                diff.modified.push(path);
+            }
+        }
+        let whiteouts = self
+            .whiteouts
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Overlay state is poisoned"))?;
+        diff.deleted = whiteouts
+            .iter()
+            .filter(|path| Path::new(path).exists())
+            .cloned()
+            .collect();
+        diff.deleted.sort();
+        Ok(diff)
+    }
+}
+
+impl SyscallMiddleware for OverlayLayer {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let Some(guest_path) = self.overlaid(call)? else {
+            return next.run(call);
+        };
+
+        match call {
+            Syscall::Openat { path, flags, .. } => {
+                if *flags & WRITE_FLAGS != 0 {
+                    let upper = self.copy_up(&guest_path, *flags & libc::O_TRUNC != 0)?;
+                    *path = to_cstring(&upper)?;
+                } else if self.is_deleted(&guest_path) {
+                    return Err(libc::ENOENT);
+                } else {
+                    let upper = self.upper_path(&guest_path);
+                    if upper.exists() {
+                        *path = to_cstring(&upper)?;
+                    }
+                }
+                next.run(call)
+            }
+            Syscall::Unlink { .. } => {
+                if self.is_deleted(&guest_path) {
+                    return Err(libc::ENOENT);
+                }
+                let upper = self.upper_path(&guest_path);
+                let in_upper = upper.is_file();
+                let in_lower = Path::new(&guest_path).exists();
+                if in_upper {
+                    std::fs::remove_file(&upper).map_err(errno)?;
+                }
+                if in_lower {
+                    self.whiteouts
+                        .lock()
+                        .map_err(|_| libc::EIO)?
+                        .insert(guest_path);
+                }
+                if in_upper || in_lower {
+                    Ok(0)
+                } else {
+                    Err(libc::ENOENT)
+                }
+            }
+            Syscall::Mkdir { path, .. } => {
+                if Path::new(&guest_path).is_dir() && !self.is_deleted(&guest_path) {
+                    return Err(libc::EEXIST);
+                }
+                let upper = self.upper_path(&guest_path);
+                if let Some(parent) = upper.parent() {
+                    std::fs::create_dir_all(parent).map_err(errno)?;
+                }
+                *path = to_cstring(&upper)?;
+                next.run(call)
+            }
+            _ => next.run(call),
+        }
+    }
+}
+
+impl Drop for OverlayLayer {
+    fn drop(&mut self) {
+        if !self.handed_out.load(Ordering::SeqCst) && self.upper.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&self.upper) {
+                log::warn!(
+                    "Failed to remove overlay directory {}: {}",
+                    self.upper.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// A host directory made absolute against the working directory and normalized
+fn host_path(dir: &Path) -> Result<PathBuf> {
+    let absolute = if dir.is_absolute() {
+        dir.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(dir)
+    };
+    let normalized = normalize_absolute(&absolute.to_string_lossy())
+        .with_context(|| format!("Invalid overlay passthrough directory {:?}", dir))?;
+    Ok(PathBuf::from(normalized))
+}
+
+/// Whether a call creates, changes or removes the file at its path
+fn modifies(call: &Syscall<'_>) -> bool {
+    match call {
+        Syscall::Openat { flags, .. } => *flags & WRITE_FLAGS != 0,
+        Syscall::Unlink { .. } | Syscall::Mkdir { .. } => true,
+        _ => false,
+    }
+}
+
+fn errno(error: std::io::Error) -> i32 {
+    error.raw_os_error().unwrap_or(libc::EIO)
+}
+
+fn to_cstring(path: &Path) -> Result<CString, i32> {
+    CString::new(path.to_string_lossy().as_bytes()).map_err(|_| libc::EINVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{read_through_chain, relative_to_cwd, write_through_chain, TempDir};
+    use std::os::fd::AsRawFd;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_overlay_keeps_host_unchanged_until_commit() {
+        let root = TempDir::new("overlay");
+        let lower = root.join("lower");
+        std::fs::create_dir_all(&lower).unwrap();
+        std::fs::write(lower.join("a.txt"), b"original").unwrap();
+        std::fs::write(lower.join("c.txt"), b"doomed").unwrap();
+        let path = |name: &str| lower.join(name).to_string_lossy().to_string();
+
+        let overlay = Arc::new(OverlayLayer::new(root.join("upper"), Vec::new()).unwrap());
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![overlay.clone()];
+
+        assert_eq!(
+            write_through_chain(&layers, &path("a.txt"), b"changed"),
+            Ok(7)
+        );
+        assert_eq!(write_through_chain(&layers, &path("b.txt"), b"new"), Ok(3));
+        let mut unlink = Syscall::Unlink {
+            path: CString::new(path("c.txt")).unwrap(),
+        };
+        assert_eq!(Next::new(&layers).run(&mut unlink), Ok(0));
+
+        // Reads see the overlay, the host does not
+        assert_eq!(read_through_chain(&layers, &path("a.txt")), b"changed");
+        let mut open_deleted = Syscall::Openat {
+            dirfd: libc::AT_FDCWD,
+            path: CString::new(path("c.txt")).unwrap(),
+            flags: libc::O_RDONLY,
+            mode: 0,
+        };
+        assert_eq!(Next::new(&layers).run(&mut open_deleted), Err(libc::ENOENT));
+        assert_eq!(std::fs::read(lower.join("a.txt")).unwrap(), b"original");
+        assert!(!lower.join("b.txt").exists());
+        assert!(lower.join("c.txt").exists());
+
+        let diff = overlay.diff().unwrap();
+        assert_eq!(diff.added, vec![path("b.txt")]);
+        assert_eq!(diff.modified, vec![path("a.txt")]);
+        assert_eq!(diff.deleted, vec![path("c.txt")]);
+
+        diff.commit().unwrap();
+        assert_eq!(std::fs::read(lower.join("a.txt")).unwrap(), b"changed");
+        assert_eq!(std::fs::read(lower.join("b.txt")).unwrap(), b"new");
+        assert!(!lower.join("c.txt").exists());
+        assert!(!root.join("upper").exists());
+    }
+
+    #[test]
+    fn test_overlay_normalizes_paths() {
+        let root = TempDir::new("overlay-paths");
+        let lower = root.join("lower");
+        let passthrough = root.join("passthrough");
+        std::fs::create_dir_all(&lower).unwrap();
+        std::fs::create_dir_all(&passthrough).unwrap();
+        let upper = root.join("upper");
+        let overlay = Arc::new(OverlayLayer::new(&upper, vec![passthrough.clone()]).unwrap());
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![overlay.clone()];
+
+        // `..` reaching into a passthrough directory writes to the host
+        let into_passthrough = format!("{}/../passthrough/p.txt", lower.display());
+        assert_eq!(write_through_chain(&layers, &into_passthrough, b"p"), Ok(1));
+        assert!(passthrough.join("p.txt").exists());
+
+        // `..` leaving a passthrough directory is overlaid
+        let out_of_passthrough = format!("{}/../lower/o.txt", passthrough.display());
+        assert_eq!(
+            write_through_chain(&layers, &out_of_passthrough, b"o"),
+            Ok(1)
+        );
+        assert!(!lower.join("o.txt").exists());
+
+        // Relative paths are resolved against the working directory
+        let relative = relative_to_cwd(&lower.join("r.txt"));
+        assert_eq!(write_through_chain(&layers, &relative, b"r"), Ok(1));
+        assert!(!lower.join("r.txt").exists());
+        assert!(upper
+            .join(lower.join("r.txt").strip_prefix("/").unwrap())
+            .exists());
+
+        // Changes relative to other directory descriptors are refused
+        let dir = std::fs::File::open(&lower).unwrap();
+        let mut open = Syscall::Openat {
+            dirfd: dir.as_raw_fd(),
+            path: CString::new("d.txt").unwrap(),
+            flags: libc::O_CREAT | libc::O_WRONLY,
+            mode: 0o644,
+        };
+        assert_eq!(Next::new(&layers).run(&mut open), Err(libc::EACCES));
+        assert!(!lower.join("d.txt").exists());
+
+        // Paths climbing above `/` are refused
+        let escape = format!("/..{}", "/..".repeat(lower.components().count()));
+        assert_eq!(
+            write_through_chain(&layers, &escape, b"x"),
+            Err(libc::EACCES)
+        );
+
+        // The upper directory goes away unless a diff was handed out
+        drop(layers);
+        drop(overlay);
+        assert!(!upper.exists());
+    }
+}
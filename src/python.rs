@@ -5,6 +5,7 @@ use pyo3::prelude::*;
 use std::sync::Arc;
 
 use crate::runtime::{Runtime, RuntimeConfig};
+use crate::source::DownloadProgress;
 
 /// Python wrapper for hyperlight-nanvix Runtime
 #[pyclass]
@@ -20,16 +21,24 @@ pub struct SandboxConfig {
     pub log_directory: Option<String>,
     #[pyo3(get, set)]
     pub tmp_directory: Option<String>,
+    /// Called as `on_progress(artifact, downloaded, total)` while binaries download
+    #[pyo3(get, set)]
+    pub on_progress: Option<PyObject>,
 }
 
 #[pymethods]
 impl SandboxConfig {
     #[new]
-    #[pyo3(signature = (log_directory=None, tmp_directory=None))]
-    fn new(log_directory: Option<String>, tmp_directory: Option<String>) -> Self {
+    #[pyo3(signature = (log_directory=None, tmp_directory=None, on_progress=None))]
+    fn new(
+        log_directory: Option<String>,
+        tmp_directory: Option<String>,
+        on_progress: Option<PyObject>,
+    ) -> Self {
         Self {
             log_directory,
             tmp_directory,
+            on_progress,
         }
     }
 }
@@ -59,7 +68,7 @@ impl NanvixSandbox {
     /// Create a new sandbox instance
     ///
     /// Args:
-    ///     config: Optional SandboxConfig with log_directory and tmp_directory
+    ///     config: Optional SandboxConfig with log_directory, tmp_directory and on_progress
     ///
     /// Returns:
     ///     A new NanvixSandbox instance
@@ -80,6 +89,22 @@ impl NanvixSandbox {
                 if let Some(tmp_dir) = cfg.tmp_directory {
                     runtime_config = runtime_config.with_tmp_directory(tmp_dir);
                 }
+                if let Some(callback) = cfg.on_progress {
+                    runtime_config = runtime_config.with_progress(Arc::new(
+                        move |progress: &DownloadProgress| {
+                            Python::with_gil(|py| {
+                                let args = (
+                                    progress.artifact.clone(),
+                                    progress.downloaded,
+                                    progress.total,
+                                );
+                                if let Err(e) = callback.call1(py, args) {
+                                    e.print(py);
+                                }
+                            });
+                        },
+                    ));
+                }
                 runtime_config
             }
             None => RuntimeConfig::new(),
@@ -110,7 +135,7 @@ impl NanvixSandbox {
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
             match runtime.run(&workload_path).await {
-                Ok(()) => Ok(WorkloadResult {
+                Ok(_) => Ok(WorkloadResult {
                     success: true,
                     error: None,
                 }),
@@ -0,0 +1,415 @@
+//! Syscall recording and deterministic replay.
+//!
+//! [`Recorder`] captures the inputs and outputs of every intercepted syscall of a
+//! run. [`Replayer`] serves a later run from such a recording instead of the host
+//! and stops at the first call that diverges from it.
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::middleware::{Next, Syscall, SyscallMiddleware, SyscallResult};
+
+/// A recorded syscall: its inputs and the result the host produced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyscallRecord {
+    pub syscall: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fd: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flags: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub len: Option<usize>,
+    /// Bytes passed in by the guest (`write`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<Vec<u8>>,
+    /// Bytes returned to the guest (`read`, `getrandom` and the clocks)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Vec<u8>>,
+    /// Return value, or -1 if the call failed
+    pub result: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
+}
+
+impl SyscallRecord {
+    /// Record the inputs of a call, leaving the outputs empty
+    fn from_call(call: &Syscall<'_>) -> Self {
+        let mut record = Self {
+            syscall: call.name().to_string(),
+            path: call.path().map(|path| path.to_string_lossy().to_string()),
+            fd: None,
+            flags: None,
+            mode: None,
+            clock: None,
+            len: None,
+            input: None,
+            output: None,
+            result: 0,
+            errno: None,
+        };
+        match call {
+            Syscall::Openat {
+                dirfd, flags, mode, ..
+            } => {
+                record.fd = Some(*dirfd);
+                record.flags = Some(*flags);
+                record.mode = Some(*mode);
+            }
+            Syscall::Mkdir { mode, .. } => record.mode = Some(*mode),
+            Syscall::Read { fd, buf } => {
+                record.fd = Some(*fd);
+                record.len = Some(buf.len());
+            }
+            Syscall::Write { fd, buf } => {
+                record.fd = Some(*fd);
+                record.len = Some(buf.len());
+                record.input = Some(buf.to_vec());
+            }
+            Syscall::Close { fd } => record.fd = Some(*fd),
+            Syscall::ClockGettime { clock, .. } => record.clock = Some(*clock),
+            Syscall::Getrandom { buf, flags } => {
+                record.flags = Some(*flags as i32);
+                record.len = Some(buf.len());
+            }
+            Syscall::Unlink { .. } | Syscall::Gettimeofday { .. } => {}
+        }
+        record
+    }
+
+    /// Whether `other` was issued with the same inputs as this record
+    fn same_inputs(&self, other: &SyscallRecord) -> bool {
+        self.syscall == other.syscall
+            && self.path == other.path
+            && self.fd == other.fd
+            && self.flags == other.flags
+            && self.mode == other.mode
+            && self.clock == other.clock
+            && self.len == other.len
+            && self.input == other.input
+    }
+
+    /// The recorded result in middleware form
+    fn syscall_result(&self) -> SyscallResult {
+        match self.errno {
+            Some(errno) => Err(errno),
+            None => Ok(self.result),
+        }
+    }
+
+    /// Short human-readable description of the call
+    fn describe(&self) -> String {
+        let mut args = Vec::new();
+        if let Some(fd) = self.fd {
+            args.push(fd.to_string());
+        }
+        if let Some(path) = &self.path {
+            args.push(format!("{:?}", path));
+        }
+        if let Some(len) = self.len {
+            args.push(format!("len={}", len));
+        }
+        format!("{}({})", self.syscall, args.join(", "))
+    }
+}
+
+/// Middleware recording every syscall that reaches it
+#[derive(Default)]
+pub struct Recorder {
+    records: Mutex<Vec<SyscallRecord>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Syscalls recorded so far
+    pub fn records(&self) -> Vec<SyscallRecord> {
+        self.records
+            .lock()
+            .map(|records| records.clone())
+            .unwrap_or_default()
+    }
+
+    /// Write the recording to `path`, one JSON record per line
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = std::io::BufWriter::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("Failed to create recording {:?}", path))?,
+        );
+        for record in self.records() {
+            serde_json::to_writer(&mut file, &record)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+impl SyscallMiddleware for Recorder {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let mut record = SyscallRecord::from_call(call);
+        let result = next.run(call);
+        match result {
+            Ok(value) => {
+                record.result = value;
+                let produced = match call {
+                    Syscall::Read { .. } | Syscall::Getrandom { .. } => {
+                        usize::try_from(value).unwrap_or(0)
+                    }
+                    _ => usize::MAX,
+                };
+                if let Some(output) = call.output_mut() {
+                    let produced = produced.min(output.len());
+                    record.output = Some(output[..produced].to_vec());
+                }
+            }
+            Err(errno) => {
+                record.result = -1;
+                record.errno = Some(errno);
+            }
+        }
+        if let Ok(mut records) = self.records.lock() {
+            records.push(record);
+        }
+        result
+    }
+}
+
+/// A syscall of a replayed run that does not match the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Position of the syscall in the run
+    pub index: usize,
+    /// What the recording expected, if it had anything left
+    pub expected: Option<SyscallRecord>,
+    /// What the guest actually issued, if anything
+    pub actual: Option<SyscallRecord>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |record: &Option<SyscallRecord>| {
+            record
+                .as_ref()
+                .map(SyscallRecord::describe)
+                .unwrap_or_else(|| "nothing".to_string())
+        };
+        write!(
+            f,
+            "syscall #{}: expected {}, got {}",
+            self.index,
+            describe(&self.expected),
+            describe(&self.actual)
+        )
+    }
+}
+
+/// Middleware serving syscalls from a recording instead of the host.
+///
+/// Syscalls are compared in lockstep with the recording: a call that matches
+/// the next recorded syscall gets its recorded result, any other call fails
+/// with `EIO` and is reported as the divergence. Once the run has diverged
+/// the recording can no longer be trusted to line up, so every later call
+/// also fails with `EIO` without being reported. Writes to the
+/// guest's stdout and stderr are still passed through so the output of the
+/// replayed run stays visible.
+pub struct Replayer {
+    records: Vec<SyscallRecord>,
+    state: Mutex<ReplayState>,
+}
+
+#[derive(Default)]
+struct ReplayState {
+    cursor: usize,
+    divergence: Option<Divergence>,
+}
+
+impl Replayer {
+    pub fn new(records: Vec<SyscallRecord>) -> Self {
+        Self {
+            records,
+            state: Mutex::new(ReplayState::default()),
+        }
+    }
+
+    /// Load a recording written by [`Recorder::save`]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open recording {:?}", path))?;
+        let mut records = Vec::new();
+        for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid record on line {} of {:?}", number + 1, path))?;
+            records.push(record);
+        }
+        Ok(Self::new(records))
+    }
+
+    /// The first divergence of the run, or the first recorded syscall the run
+    /// never issued
+    pub fn divergence(&self) -> Option<Divergence> {
+        let state = self.state.lock().ok()?;
+        if let Some(divergence) = &state.divergence {
+            return Some(divergence.clone());
+        }
+        self.records.get(state.cursor).map(|record| Divergence {
+            index: state.cursor,
+            expected: Some(record.clone()),
+            actual: None,
+        })
+    }
+
+    /// Report the divergence of a replayed run, if any, in place of its
+    /// outcome. Calls after a divergence fail with `EIO`, so the guest failing
+    /// is usually a consequence of the divergence rather than its cause.
+    pub fn check(&self, run_result: Result<()>) -> Result<()> {
+        let Some(divergence) = self.divergence() else {
+            return run_result;
+        };
+        let message = format!("Replay diverged from the recording at {}", divergence);
+        match run_result {
+            Ok(()) => Err(anyhow::anyhow!(message)),
+            Err(error) => Err(error.context(message)),
+        }
+    }
+}
+
+impl SyscallMiddleware for Replayer {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        let actual = SyscallRecord::from_call(call);
+        let Ok(mut state) = self.state.lock() else {
+            return Err(libc::EIO);
+        };
+        if state.divergence.is_some() {
+            return Err(libc::EIO);
+        }
+        let index = state.cursor;
+        let expected = self.records.get(index);
+        state.cursor += 1;
+
+        match expected {
+            Some(expected) if expected.same_inputs(&actual) => {
+                drop(state);
+                if let (Some(buf), Some(output)) = (call.output_mut(), &expected.output) {
+                    let len = output.len().min(buf.len());
+                    buf[..len].copy_from_slice(&output[..len]);
+                }
+                if let Syscall::Write { fd: 1 | 2, .. } = call {
+                    let _ = next.run(call);
+                }
+                expected.syscall_result()
+            }
+            _ => {
+                state.divergence = Some(Divergence {
+                    index,
+                    expected: expected.cloned(),
+                    actual: Some(actual),
+                });
+                Err(libc::EIO)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{read_through_chain, TempDir};
+    use std::ffi::CString;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_syscall_record_and_replay() {
+        let root = TempDir::new("replay");
+        let path = root.join("contents").to_string_lossy().to_string();
+        std::fs::write(&path, b"recorded contents").unwrap();
+
+        let recorder = Arc::new(Recorder::new());
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![recorder.clone()];
+        assert_eq!(read_through_chain(&layers, &path), b"recorded contents");
+        assert_eq!(recorder.records().len(), 3);
+
+        // The replayed run must not touch the host file
+        std::fs::remove_file(&path).unwrap();
+
+        let replayer = Arc::new(Replayer::new(recorder.records()));
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![replayer.clone()];
+        assert_eq!(read_through_chain(&layers, &path), b"recorded contents");
+        assert!(replayer.divergence().is_none());
+    }
+
+    #[test]
+    fn test_syscall_replay_flags_divergence() {
+        let recorder = Arc::new(Recorder::new());
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![recorder.clone()];
+        let mut call = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix-a").unwrap(),
+        };
+        assert_eq!(Next::new(&layers).run(&mut call), Err(libc::ENOENT));
+
+        let replayer = Arc::new(Replayer::new(recorder.records()));
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![replayer.clone()];
+        let mut call = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix-b").unwrap(),
+        };
+        assert_eq!(Next::new(&layers).run(&mut call), Err(libc::EIO));
+
+        // Calls after the divergence fail without being reported
+        let mut call = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix-c").unwrap(),
+        };
+        assert_eq!(Next::new(&layers).run(&mut call), Err(libc::EIO));
+
+        let divergence = replayer.divergence().unwrap();
+        assert_eq!(divergence.index, 0);
+        assert_eq!(
+            divergence.actual.as_ref().unwrap().path.as_deref(),
+            Some("/nonexistent/hyperlight-nanvix-b")
+        );
+    }
+
+    #[test]
+    fn test_syscall_replay_reports_divergence_of_failed_run() {
+        // Without a divergence the outcome of the run is kept
+        let replayer = Replayer::new(Vec::new());
+        assert!(replayer.check(Ok(())).is_ok());
+        assert_eq!(
+            replayer
+                .check(Err(anyhow::anyhow!("guest failed")))
+                .unwrap_err()
+                .to_string(),
+            "guest failed"
+        );
+
+        let replayer = Arc::new(Replayer::new(Vec::new()));
+        let layers: Vec<Arc<dyn SyscallMiddleware>> = vec![replayer.clone()];
+        let mut call = Syscall::Unlink {
+            path: CString::new("/nonexistent/hyperlight-nanvix-d").unwrap(),
+        };
+        assert_eq!(Next::new(&layers).run(&mut call), Err(libc::EIO));
+
+        // The guest fails because of the divergence, which must still be reported
+        let error = replayer
+            .check(Err(anyhow::anyhow!("guest failed")))
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Replay diverged"));
+        assert_eq!(error.root_cause().to_string(), "guest failed");
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::path::Path;
 
 use nanvix::log;
@@ -6,10 +6,19 @@ use nanvix::registry::Registry;
 use nanvix::sandbox_cache::SandboxCacheConfig;
 use nanvix::terminal::Terminal;
 
-use crate::cache;
+use crate::artifacts::{Artifact, ArtifactCollector, ArtifactsConfig};
+use crate::cache::{self, Integrity, Verification};
+use crate::capture::{OutputCapture, Violation, ViolationTracker, MAX_CAPTURED_OUTPUT};
+use crate::console::{ConsoleLayer, LineReader};
+use crate::deterministic::{Deterministic, DeterministicLayer};
+use crate::diagnose::{self, Check, CheckStatus, Diagnosis};
+use crate::middleware::{self, SyscallMiddleware};
+use crate::overlay::{OverlayDiff, OverlayLayer};
+use crate::replay::{Recorder, Replayer};
+use crate::source::{ArtifactSource, ProgressCallback, RegistrySource};
 
 /// Supported workload types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum WorkloadType {
     JavaScript,
     Python,
@@ -59,6 +68,54 @@ impl WorkloadType {
     }
 }
 
+/// Outcome of a successful workload run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunResult {
+    /// Files the guest wrote below the artifacts directory
+    pub artifacts: Vec<Artifact>,
+    /// Filesystem changes the guest made, in overlay mode
+    pub overlay: Option<OverlayDiff>,
+    /// What the guest wrote to stdout, when output is captured
+    pub stdout: Vec<u8>,
+    /// What the guest wrote to stderr, when output is captured
+    pub stderr: Vec<u8>,
+    /// Whether captured output was cut off at [`MAX_CAPTURED_OUTPUT`] bytes per stream
+    pub output_truncated: bool,
+    /// Syscalls the middleware denied
+    pub violations: Vec<Violation>,
+    /// Time the guest ran for
+    pub duration: std::time::Duration,
+    /// Kernel the sandbox booted
+    pub kernel: Option<BinaryVersion>,
+    /// Interpreter the script ran in
+    pub interpreter: Option<BinaryVersion>,
+}
+
+/// A kernel or interpreter binary used for a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryVersion {
+    pub path: String,
+    /// Pinned Nanvix release the binary was cached for
+    pub release: Option<String>,
+    /// SHA-256 digest recorded in the cache manifest, if any
+    pub sha256: Option<String>,
+}
+
+/// Error of a workload that failed in the guest, with what the run produced
+#[derive(Debug)]
+pub struct RunFailure {
+    pub error: anyhow::Error,
+    pub result: RunResult,
+}
+
+impl std::fmt::Display for RunFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for RunFailure {}
+
 /// Runtime configuration for hyperlight-nanvix
 #[derive(Clone)]
 pub struct RuntimeConfig {
@@ -66,8 +123,38 @@ pub struct RuntimeConfig {
     pub syscall_table: Option<std::sync::Arc<nanvix::sandbox::SyscallTable<()>>>,
     /// Directory for storing logs
     pub log_directory: String,
-    /// Directory for temporary files
+    /// Directory for temporary files, including the sandbox toolchain and snapshot
     pub tmp_directory: String,
+    /// Syscall middleware layers, outermost first
+    pub middleware: Vec<std::sync::Arc<dyn SyscallMiddleware>>,
+    /// File to record the run's syscalls to
+    pub syscall_recording: Option<String>,
+    /// Recording to serve the run's syscalls from instead of the host
+    pub syscall_replay: Option<String>,
+    /// Fixed clock and randomness for reproducible runs
+    pub deterministic: Option<Deterministic>,
+    /// Guest output directory collected after each run
+    pub artifacts: Option<ArtifactsConfig>,
+    /// Redirect guest filesystem changes into a per-run upper directory
+    pub overlay: bool,
+    /// Directory of the registry cache (defaults to [`cache::get_cache_directory`])
+    pub cache_directory: Option<String>,
+    /// Nanvix release to run against, kept in its own cache directory
+    pub nanvix_release: Option<String>,
+    /// Local artifact directory used instead of the registry, for offline hosts
+    pub offline_directory: Option<String>,
+    /// How cached binaries are checked against the cache manifest before boot
+    pub verification: Verification,
+    /// Where uncached binaries are fetched from (defaults to the upstream registry)
+    pub artifact_source: Option<std::sync::Arc<dyn ArtifactSource>>,
+    /// Kernel booted instead of the cached `kernel.elf`
+    pub kernel_path: Option<String>,
+    /// Interpreters used instead of the cached ones, per workload type
+    pub interpreter_paths: std::collections::HashMap<WorkloadType, String>,
+    /// Callback receiving the progress of binary downloads
+    pub progress: Option<ProgressCallback>,
+    /// Return the guest's stdout and stderr in the run result instead of printing them
+    pub capture_output: bool,
 }
 
 impl std::fmt::Debug for RuntimeConfig {
@@ -79,6 +166,24 @@ impl std::fmt::Debug for RuntimeConfig {
             )
             .field("log_directory", &self.log_directory)
             .field("tmp_directory", &self.tmp_directory)
+            .field("middleware", &self.middleware.len())
+            .field("syscall_recording", &self.syscall_recording)
+            .field("syscall_replay", &self.syscall_replay)
+            .field("deterministic", &self.deterministic)
+            .field("artifacts", &self.artifacts)
+            .field("overlay", &self.overlay)
+            .field("cache_directory", &self.cache_directory)
+            .field("nanvix_release", &self.nanvix_release)
+            .field("offline_directory", &self.offline_directory)
+            .field("verification", &self.verification)
+            .field("capture_output", &self.capture_output)
+            .field(
+                "artifact_source",
+                &self
+                    .artifact_source
+                    .as_ref()
+                    .map(|source| source.describe()),
+            )
             .finish()
     }
 }
@@ -89,6 +194,21 @@ impl Default for RuntimeConfig {
             syscall_table: None,
             log_directory: "/tmp/hyperlight-nanvix".to_string(),
             tmp_directory: "/tmp/hyperlight-nanvix".to_string(),
+            middleware: Vec::new(),
+            syscall_recording: None,
+            syscall_replay: None,
+            deterministic: None,
+            artifacts: None,
+            overlay: false,
+            cache_directory: None,
+            nanvix_release: None,
+            offline_directory: None,
+            verification: Verification::default(),
+            artifact_source: None,
+            kernel_path: None,
+            interpreter_paths: std::collections::HashMap::new(),
+            progress: None,
+            capture_output: false,
         }
     }
 }
@@ -115,23 +235,384 @@ impl RuntimeConfig {
         self.tmp_directory = dir.into();
         self
     }
+
+    pub fn with_cache_directory<S: Into<String>>(mut self, dir: S) -> Self {
+        self.cache_directory = Some(dir.into());
+        self
+    }
+
+    /// Pin the Nanvix release (e.g. `"v1.2"`) whose kernel and interpreters are used.
+    ///
+    /// Each release is cached in its own directory, so runtimes pinned to different
+    /// releases can run side by side in the same process. Uncached binaries are
+    /// fetched from the release's directory of the artifact source; the upstream
+    /// registry serves only its current release, so [`Runtime::new`] rejects a
+    /// pinned release without [`with_artifact_source`](Self::with_artifact_source)
+    /// or [`with_offline_directory`](Self::with_offline_directory).
+    pub fn with_nanvix_release<S: Into<String>>(mut self, release: S) -> Self {
+        self.nanvix_release = Some(release.into());
+        self
+    }
+
+    /// Resolve the kernel, interpreters and Python sysroot only from `dir`.
+    ///
+    /// The directory has the layout of the registry cache (`bin/kernel.elf`,
+    /// `bin/qjs`, `bin/python3`, `lib/python3.12`); the registry is never contacted.
+    pub fn with_offline_directory<S: Into<String>>(mut self, dir: S) -> Self {
+        self.offline_directory = Some(dir.into());
+        self
+    }
+
+    /// Choose how cached binaries are verified before boot (full hashing by default)
+    pub fn with_verification(mut self, verification: Verification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Fetch uncached binaries from `source` instead of the upstream registry
+    pub fn with_artifact_source(mut self, source: std::sync::Arc<dyn ArtifactSource>) -> Self {
+        self.artifact_source = Some(source);
+        self
+    }
+
+    /// Report the progress of binary downloads to `callback`
+    pub fn with_progress(mut self, callback: ProgressCallback) -> Self {
+        self.progress = Some(callback);
+        self
+    }
+
+    /// Boot the kernel at `path` instead of the cached `kernel.elf`, e.g. a local Nanvix build
+    pub fn with_kernel_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.kernel_path = Some(path.into());
+        self
+    }
+
+    /// Run `workload_type` workloads with the interpreter at `path` instead of the cached one.
+    ///
+    /// A custom Python interpreter runs with the sysroot at `<path>/../../lib/python3.12`,
+    /// which must exist.
+    pub fn with_interpreter_path<S: Into<String>>(
+        mut self,
+        workload_type: WorkloadType,
+        path: S,
+    ) -> Self {
+        self.interpreter_paths.insert(workload_type, path.into());
+        self
+    }
+
+    /// The custom path configured for a kernel or interpreter binary, if any
+    fn binary_override(&self, binary_name: &str) -> Option<&str> {
+        if binary_name == cache::KERNEL_BINARY {
+            return self.kernel_path.as_deref();
+        }
+        self.interpreter_paths
+            .iter()
+            .find(|(workload_type, _)| workload_type.binary_name() == binary_name)
+            .map(|(_, path)| path.as_str())
+    }
+
+    /// The registry cache directory in effect for this configuration
+    pub fn resolve_cache_directory(&self) -> std::path::PathBuf {
+        match &self.cache_directory {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => cache::get_cache_directory(),
+        }
+    }
+
+    /// The directory holding the binaries of the configured release
+    pub fn resolve_release_directory(&self) -> std::path::PathBuf {
+        let cache_directory = self.resolve_cache_directory();
+        match &self.nanvix_release {
+            Some(release) => cache::get_release_directory(&cache_directory, release),
+            None => cache_directory,
+        }
+    }
+
+    /// Append a layer to the syscall middleware chain.
+    ///
+    /// Layers see each syscall in the order they were added; the chain ends at
+    /// the default forwarder.
+    /// Runs with middleware are serialized process-wide; see
+    /// [`SyscallMiddleware`].
+    pub fn with_syscall_middleware(mut self, layer: std::sync::Arc<dyn SyscallMiddleware>) -> Self {
+        self.middleware.push(layer);
+        self
+    }
+
+    /// Record every intercepted syscall of each run to `path`
+    pub fn with_syscall_recording<S: Into<String>>(mut self, path: S) -> Self {
+        self.syscall_recording = Some(path.into());
+        self
+    }
+
+    /// Serve intercepted syscalls from a recording instead of the host.
+    ///
+    /// The run fails if the guest diverges from the recording.
+    pub fn with_syscall_replay<S: Into<String>>(mut self, path: S) -> Self {
+        self.syscall_replay = Some(path.into());
+        self
+    }
+
+    /// Give the guest a virtual clock starting at `epoch` and random bytes seeded by `seed`
+    pub fn with_deterministic(mut self, deterministic: Deterministic) -> Self {
+        self.deterministic = Some(deterministic);
+        self
+    }
+
+    /// Collect the files the guest writes below a directory as run artifacts
+    pub fn with_artifacts(mut self, artifacts: ArtifactsConfig) -> Self {
+        self.artifacts = Some(artifacts);
+        self
+    }
+
+    /// Let the guest write freely without changing the host.
+    ///
+    /// Writes, creates and deletions go to a per-run upper directory under the
+    /// tmp directory; the resulting changes are returned in the run result.
+    pub fn with_overlay(mut self, enabled: bool) -> Self {
+        self.overlay = enabled;
+        self
+    }
+
+    /// Return the guest's stdout and stderr in the run result instead of printing them.
+    ///
+    /// At most [`MAX_CAPTURED_OUTPUT`] bytes of each stream are kept.
+    pub fn with_capture_output(mut self, enabled: bool) -> Self {
+        self.capture_output = enabled;
+        self
+    }
+
+    /// Whether syscalls are routed through the middleware chain
+    fn intercepts_syscalls(&self) -> bool {
+        !self.middleware.is_empty()
+            || self.syscall_recording.is_some()
+            || self.syscall_replay.is_some()
+            || self.deterministic.is_some()
+            || self.artifacts.is_some()
+            || self.overlay
+            || self.capture_output
+    }
 }
 
 /// Runtime for executing workloads in Nanvix sandboxes
 pub struct Runtime {
     config: RuntimeConfig,
     registry: Registry,
+    cache_directory: std::path::PathBuf,
+    release_directory: std::path::PathBuf,
+    source: std::sync::Arc<dyn ArtifactSource>,
 }
 
 impl Runtime {
     pub fn new(config: RuntimeConfig) -> Result<Self> {
-        let registry = Registry::new(None);
-        Ok(Self { config, registry })
+        if config.syscall_table.is_some() && config.intercepts_syscalls() {
+            anyhow::bail!("A custom syscall table cannot be combined with syscall middleware");
+        }
+        if config.syscall_recording.is_some() && config.syscall_replay.is_some() {
+            anyhow::bail!("Syscall recording and replay cannot be enabled at the same time");
+        }
+        if let Some(release) = &config.nanvix_release {
+            if release.is_empty() || release.contains(['/', '\\']) || release.starts_with('.') {
+                anyhow::bail!("Invalid Nanvix release name {:?}", release);
+            }
+        }
+        let cache_directory = config.resolve_cache_directory();
+        let release_directory = config.resolve_release_directory();
+        let registry = Registry::new(Some(release_directory.to_string_lossy().to_string()));
+        let mut source = config
+            .artifact_source
+            .clone()
+            .unwrap_or_else(|| std::sync::Arc::new(RegistrySource));
+        // Offline runtimes never fetch, so they need no source for the release
+        if let (Some(release), None) = (&config.nanvix_release, &config.offline_directory) {
+            source = source.release(release)?;
+        }
+        Ok(Self {
+            config,
+            registry,
+            cache_directory,
+            release_directory,
+            source,
+        })
+    }
+
+    /// Directory of the registry cache used by this runtime
+    pub fn cache_directory(&self) -> &Path {
+        &self.cache_directory
+    }
+
+    /// Directory of the Nanvix release used by this runtime
+    pub fn release_directory(&self) -> &Path {
+        &self.release_directory
+    }
+
+    /// Directory binaries are resolved from: the offline directory, or the release directory
+    fn artifact_root(&self) -> &Path {
+        match &self.config.offline_directory {
+            Some(dir) => Path::new(dir),
+            None => &self.release_directory,
+        }
     }
 
     /// Check if a binary exists in the cache and return its path if found
     async fn get_cached_binary_path(&self, binary_name: &str) -> Option<String> {
-        cache::get_cached_binary_path(binary_name).await
+        cache::get_cached_binary_path(self.artifact_root(), binary_name).await
+    }
+
+    /// Fetch a binary from the artifact source and record its digest
+    async fn fetch_binary(&self, binary_name: &str) -> Result<String> {
+        if let Some(dir) = &self.config.offline_directory {
+            anyhow::bail!("Offline mode: {} is missing from {}", binary_name, dir);
+        }
+        let path = cache::fetch_binary(
+            &self.release_directory,
+            self.source.as_ref(),
+            binary_name,
+            self.config.progress.as_ref(),
+        )
+        .await?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    /// Check a cached binary against the manifest, re-fetching it on a mismatch
+    async fn verify_binary(&self, binary_name: &str, path: String) -> Result<String> {
+        if self.config.verification == Verification::Disabled {
+            return Ok(path);
+        }
+        let root = self.artifact_root();
+        let manifest = cache::Manifest::load(root)?;
+        match manifest.verify(binary_name, Path::new(&path), self.config.verification)? {
+            Integrity::Valid => Ok(path),
+            // Binaries cached before the manifest existed are trusted once
+            Integrity::Unrecorded if self.config.offline_directory.is_none() => {
+                log::warn!(
+                    "{} has no recorded digest; trusting it and recording its digest",
+                    path
+                );
+                cache::record_binary(root, binary_name, Path::new(&path)).await?;
+                Ok(path)
+            }
+            Integrity::Unrecorded => {
+                log::warn!("{} has no recorded digest and cannot be verified", path);
+                Ok(path)
+            }
+            Integrity::Mismatch { expected, actual } => {
+                if self.config.offline_directory.is_some() {
+                    anyhow::bail!(
+                        "{} failed integrity verification: expected sha256 {}, found {}",
+                        path,
+                        expected,
+                        actual
+                    );
+                }
+                log::warn!(
+                    "{} failed integrity verification (expected sha256 {}, found {}), re-fetching...",
+                    path,
+                    expected,
+                    actual
+                );
+                let path = cache::refetch_binary(
+                    &self.release_directory,
+                    self.source.as_ref(),
+                    binary_name,
+                    &actual,
+                    self.config.progress.as_ref(),
+                )
+                .await?;
+                Ok(path.to_string_lossy().to_string())
+            }
+        }
+    }
+
+    /// Path of a verified binary, downloading it if it is not cached
+    async fn resolve_binary(&self, binary_name: &str) -> Result<String> {
+        if let Some(path) = self.config.binary_override(binary_name) {
+            if !Path::new(path).is_file() {
+                anyhow::bail!("Custom {} binary {} does not exist", binary_name, path);
+            }
+            log::info!("Using custom {} binary: {}", binary_name, path);
+            return Ok(path.to_string());
+        }
+        if let Some(cached_path) = self.get_cached_binary_path(binary_name).await {
+            log::info!("Using cached {} binary: {}", binary_name, cached_path);
+            self.verify_binary(binary_name, cached_path).await
+        } else {
+            log::info!("{} not cached, downloading from registry...", binary_name);
+            self.fetch_binary(binary_name).await
+        }
+    }
+
+    /// Version information of a binary returned by `resolve_binary`
+    fn binary_version(&self, binary_name: &str, path: &str) -> Result<BinaryVersion> {
+        if self.config.binary_override(binary_name).is_some() {
+            return Ok(BinaryVersion {
+                path: path.to_string(),
+                release: None,
+                sha256: None,
+            });
+        }
+        let manifest = cache::Manifest::load(self.artifact_root())?;
+        Ok(BinaryVersion {
+            path: path.to_string(),
+            release: match &self.config.offline_directory {
+                Some(_) => None,
+                None => self.config.nanvix_release.clone(),
+            },
+            sha256: manifest
+                .binaries
+                .get(binary_name)
+                .map(|entry| entry.sha256.clone()),
+        })
+    }
+
+    /// Artifacts `workload_type` needs that are absent from the offline directory
+    fn missing_offline_artifacts(
+        &self,
+        offline_dir: &str,
+        workload_type: WorkloadType,
+    ) -> Vec<String> {
+        cache::missing_artifacts(Path::new(offline_dir), workload_type)
+            .into_iter()
+            .filter(|artifact| match artifact.strip_prefix("bin/") {
+                Some(binary_name) => self.config.binary_override(binary_name).is_none(),
+                // A custom Python interpreter brings its own sysroot
+                None => self
+                    .config
+                    .binary_override(workload_type.binary_name())
+                    .is_none(),
+            })
+            .collect()
+    }
+
+    /// Fetch and verify the binaries of `workload_types` ahead of the first run,
+    /// so services can warm exactly what they use at startup
+    pub async fn prefetch(&self, workload_types: &[WorkloadType]) -> Result<()> {
+        if let Some(offline_dir) = &self.config.offline_directory {
+            let mut missing = Vec::new();
+            for workload_type in workload_types {
+                for artifact in self.missing_offline_artifacts(offline_dir, *workload_type) {
+                    if !missing.contains(&artifact) {
+                        missing.push(artifact);
+                    }
+                }
+            }
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "Offline mode: missing artifacts in {}: {}",
+                    offline_dir,
+                    missing.join(", ")
+                );
+            }
+        }
+        for binary_name in cache::required_binaries(workload_types) {
+            self.resolve_binary(binary_name).await?;
+        }
+        Ok(())
+    }
+
+    /// Binaries published by the artifact source; the upstream registry publishes no list
+    pub async fn available_binaries(&self) -> Result<Vec<String>> {
+        self.source.list().await
     }
 
     /// Clear the nanvix registry cache to force fresh downloads
@@ -142,108 +623,425 @@ impl Runtime {
         Ok(())
     }
 
+    /// Check the host prerequisites of this runtime: KVM access, writable
+    /// directories and the binaries in the cache, with a fix for each problem.
+    ///
+    /// Nothing is created or modified on the host.
+    pub fn diagnose(&self) -> Diagnosis {
+        let mut checks = diagnose::check_kvm(Path::new(diagnose::KVM_DEVICE));
+        checks.push(diagnose::check_writable(
+            "tmp directory",
+            Path::new(&self.config.tmp_directory),
+            "Pick a writable directory with `--tmp-dir` or `RuntimeConfig::with_tmp_directory`",
+        ));
+        checks.push(diagnose::check_writable(
+            "log directory",
+            Path::new(&self.config.log_directory),
+            "Pick a writable directory with `--log-dir` or `RuntimeConfig::with_log_directory`",
+        ));
+        if self.config.offline_directory.is_none() {
+            checks.push(diagnose::check_writable(
+                "cache directory",
+                &self.release_directory,
+                "Pick a writable directory with `--cache-dir` or the HYPERLIGHT_NANVIX_CACHE environment variable",
+            ));
+        }
+        for binary_name in cache::KNOWN_BINARIES {
+            checks.push(self.check_binary(binary_name));
+        }
+        checks.push(self.check_python_sysroot());
+
+        let artifacts = match &self.config.offline_directory {
+            Some(dir) => cache::list_binaries(Path::new(dir)),
+            None => cache::list_binaries(&self.cache_directory),
+        };
+        let artifacts = match artifacts {
+            Ok(artifacts) => artifacts,
+            Err(e) => {
+                checks.push(Check::problem(
+                    "cached artifacts",
+                    CheckStatus::Fail,
+                    format!("{:#}", e),
+                    "Clear the cache with `hyperlight-nanvix registry clear`",
+                ));
+                Vec::new()
+            }
+        };
+
+        Diagnosis {
+            nanvix_revision: diagnose::NANVIX_REVISION.to_string(),
+            checks,
+            artifacts,
+        }
+    }
+
+    /// Check that `binary_name` is available and matches the cache manifest
+    fn check_binary(&self, binary_name: &str) -> Check {
+        if let Some(path) = self.config.binary_override(binary_name) {
+            return if Path::new(path).is_file() {
+                Check::pass(binary_name, format!("custom binary {}", path))
+            } else {
+                Check::problem(
+                    binary_name,
+                    CheckStatus::Fail,
+                    format!("custom binary {} does not exist", path),
+                    "Point the override at an existing file",
+                )
+            };
+        }
+        // Online, missing or corrupted binaries are fetched again on the next run
+        let (status, fix) = match &self.config.offline_directory {
+            Some(dir) => (
+                CheckStatus::Fail,
+                format!("Copy {} into {}/bin", binary_name, dir),
+            ),
+            // The kernel is set up along with any selection of binaries
+            None if binary_name == cache::KERNEL_BINARY => (
+                CheckStatus::Warn,
+                "Fetch it with `hyperlight-nanvix registry setup`".to_string(),
+            ),
+            None => (
+                CheckStatus::Warn,
+                format!(
+                    "Fetch it with `hyperlight-nanvix registry setup --only {}`",
+                    binary_name
+                ),
+            ),
+        };
+        let root = self.artifact_root();
+        let path = cache::get_binary_cache_directory(root).join(binary_name);
+        if !path.is_file() {
+            return Check::problem(
+                binary_name,
+                status,
+                format!("not found in {}", path.display()),
+                fix,
+            );
+        }
+        let integrity = cache::Manifest::load(root)
+            .and_then(|manifest| manifest.verify(binary_name, &path, self.config.verification));
+        match integrity {
+            Ok(Integrity::Valid) => {
+                Check::pass(binary_name, format!("{} verified", path.display()))
+            }
+            Ok(Integrity::Unrecorded) => Check::pass(
+                binary_name,
+                format!("{} (not in the manifest)", path.display()),
+            ),
+            Ok(Integrity::Mismatch { expected, actual }) => Check::problem(
+                binary_name,
+                status,
+                format!(
+                    "{} failed integrity verification: expected sha256 {}, found {}",
+                    path.display(),
+                    expected,
+                    actual
+                ),
+                fix,
+            ),
+            Err(e) => Check::problem(binary_name, status, format!("{:#}", e), fix),
+        }
+    }
+
+    /// Check that the Python standard library is available
+    fn check_python_sysroot(&self) -> Check {
+        if let Some(interpreter) = self.config.binary_override("python3") {
+            let interpreter = Path::new(interpreter);
+            let path = python_base(interpreter).map(|base| base.join(cache::PYTHON_SYSROOT));
+            return match path {
+                Some(path) if path.is_dir() => {
+                    Check::pass("python sysroot", format!("{} exists", path.display()))
+                }
+                _ => Check::problem(
+                    "python sysroot",
+                    CheckStatus::Fail,
+                    format!(
+                        "no {} next to {}",
+                        cache::PYTHON_SYSROOT,
+                        interpreter.display()
+                    ),
+                    "Place the sysroot at `<interpreter>/../../lib/python3.12`",
+                ),
+            };
+        }
+        let path = self.artifact_root().join(cache::PYTHON_SYSROOT);
+        if path.is_dir() {
+            return Check::pass("python sysroot", format!("{} exists", path.display()));
+        }
+        match &self.config.offline_directory {
+            Some(dir) => Check::problem(
+                "python sysroot",
+                CheckStatus::Fail,
+                format!("{} does not exist", path.display()),
+                format!("Copy {} into {}", cache::PYTHON_SYSROOT, dir),
+            ),
+            None => Check::problem(
+                "python sysroot",
+                CheckStatus::Warn,
+                format!("{} does not exist", path.display()),
+                "Fetch it with `hyperlight-nanvix registry setup --only python3`",
+            ),
+        }
+    }
+
     /// Run a workload
-    pub async fn run<P: AsRef<Path>>(&self, workload_path: P) -> Result<()> {
+    pub async fn run<P: AsRef<Path>>(&self, workload_path: P) -> Result<RunResult> {
+        self.run_with_args(workload_path, &[]).await
+    }
+
+    /// Run `code` as a JavaScript or Python script, passing `guest_args` to it
+    ///
+    /// The code is staged in the tmp directory for the duration of the run.
+    pub async fn run_code(
+        &self,
+        workload_type: WorkloadType,
+        code: &str,
+        guest_args: &[String],
+    ) -> Result<RunResult> {
+        let extension = match workload_type {
+            WorkloadType::Binary => anyhow::bail!("Inline code must be JavaScript or Python"),
+            _ => workload_type.extensions()[0],
+        };
+
+        let staging = create_run_directory(Path::new(&self.config.tmp_directory), "inline")?;
+        let script_path = staging.join(format!("main.{}", extension));
+        std::fs::write(&script_path, code)?;
+
+        let result = self.run_with_args(&script_path, guest_args).await;
+
+        if let Err(e) = std::fs::remove_dir_all(&staging) {
+            log::warn!("Failed to remove staged code {}: {}", staging.display(), e);
+        }
+        result
+    }
+
+    /// Run a workload, passing `guest_args` to the guest program
+    ///
+    /// Scripts see the arguments after the script path (`sys.argv[1:]`,
+    /// `scriptArgs`); binaries receive them as their command line.
+    pub async fn run_with_args<P: AsRef<Path>>(
+        &self,
+        workload_path: P,
+        guest_args: &[String],
+    ) -> Result<RunResult> {
         let workload_path = workload_path.as_ref();
 
+        // The guest command line is split on whitespace
+        if let Some(arg) = guest_args
+            .iter()
+            .find(|arg| arg.is_empty() || arg.contains(char::is_whitespace))
+        {
+            anyhow::bail!(
+                "Guest argument {:?} is empty or contains whitespace, which the guest cannot receive",
+                arg
+            );
+        }
+
         // Determine workload type from file extension
         let workload_type = WorkloadType::from_path(workload_path).ok_or_else(|| {
             anyhow::anyhow!("Could not determine workload type for {:?}", workload_path)
         })?;
 
-        // Use hardcoded values for machine and deployment type (hyperlight single-process)
-        let machine_type = "hyperlight";
-        let deployment_type = "single-process";
+        self.execute(workload_type, Some(workload_path), guest_args, None)
+            .await
+    }
+
+    /// Start the interactive interpreter of `workload_type`, reading its input from `reader`
+    ///
+    /// Returns once the interpreter exits, e.g. after `reader` reaches end of input.
+    ///
+    /// The REPL runs through the syscall middleware, and runs that use middleware
+    /// are serialized process-wide: no other such run, of this or any other
+    /// `Runtime`, starts until the REPL exits. `reader` is called while the console
+    /// state and that lock are held, so a `read_line` waiting for input blocks them.
+    pub async fn repl(
+        &self,
+        workload_type: WorkloadType,
+        reader: Box<dyn LineReader>,
+    ) -> Result<RunResult> {
+        if matches!(workload_type, WorkloadType::Binary) {
+            anyhow::bail!("Only JavaScript and Python have an interactive interpreter");
+        }
+        if self.config.syscall_table.is_some() {
+            anyhow::bail!("The REPL cannot be used with a custom syscall table");
+        }
+        let console = std::sync::Arc::new(ConsoleLayer::new(reader));
+        let result = self
+            .execute(workload_type, None, &[], Some(console.clone()))
+            .await;
+        console.flush();
+        result
+    }
+
+    /// Run `workload_path`, or the interpreter's REPL attached to `console` when there is none
+    async fn execute(
+        &self,
+        workload_type: WorkloadType,
+        workload_path: Option<&Path>,
+        guest_args: &[String],
+        console: Option<std::sync::Arc<ConsoleLayer>>,
+    ) -> Result<RunResult> {
+        // In offline mode, report everything the workload needs that is absent
+        if let Some(offline_dir) = &self.config.offline_directory {
+            let missing = self.missing_offline_artifacts(offline_dir, workload_type);
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "Offline mode: missing artifacts in {}: {}",
+                    offline_dir,
+                    missing.join(", ")
+                );
+            }
+        }
 
         // Get interpreter binary (only needed for scripted workloads)
         let binary_path = if matches!(workload_type, WorkloadType::Binary) {
             // For binary workloads, we don't need an interpreter
             String::new()
-        } else if let Some(cached_path) = self
-            .get_cached_binary_path(workload_type.binary_name())
-            .await
-        {
-            log::info!(
-                "Using cached {} binary: {}",
-                workload_type.binary_name(),
-                cached_path
-            );
-            cached_path
         } else {
-            log::info!(
-                "{} not cached, downloading from registry...",
-                workload_type.binary_name()
-            );
-            self.registry
-                .get_cached_binary(machine_type, deployment_type, workload_type.binary_name())
-                .await?
+            self.resolve_binary(workload_type.binary_name()).await?
         };
 
         // Get kernel path for terminal configuration
-        let kernel_path = if let Some(cached_path) = self.get_cached_binary_path("kernel.elf").await
-        {
-            log::info!("Using cached kernel binary: {}", cached_path);
-            cached_path
-        } else {
-            log::info!("kernel.elf not cached, downloading from registry...");
-            self.registry
-                .get_cached_binary(machine_type, deployment_type, "kernel.elf")
-                .await?
+        let kernel_path = self.resolve_binary(cache::KERNEL_BINARY).await?;
+        if self.config.offline_directory.is_none() {
+            cache::mark_used(&self.release_directory).await?;
+        }
+        let kernel = Some(self.binary_version(cache::KERNEL_BINARY, &kernel_path)?);
+        let interpreter = match workload_type {
+            WorkloadType::Binary => None,
+            _ => Some(self.binary_version(workload_type.binary_name(), &binary_path)?),
+        };
+
+        // Python runs from the directory holding its sysroot, two levels above the
+        // interpreter. A custom interpreter must bring its own sysroot.
+        let python_base = match workload_type {
+            WorkloadType::Python => {
+                let interpreter = std::fs::canonicalize(&binary_path).with_context(|| {
+                    format!("Failed to resolve the Python interpreter {}", binary_path)
+                })?;
+                let custom = self.config.binary_override("python3").is_some();
+                let base = python_base(&interpreter)
+                    .filter(|base| !custom || base.join(cache::PYTHON_SYSROOT).is_dir())
+                    .with_context(|| {
+                        format!(
+                            "No Python sysroot {} next to the interpreter {}",
+                            cache::PYTHON_SYSROOT,
+                            interpreter.display()
+                        )
+                    })?
+                    .to_path_buf();
+                Some((interpreter, base))
+            }
+            _ => None,
         };
 
         // Ensure the temporary directory exists for socket creation
         std::fs::create_dir_all(&self.config.tmp_directory)?;
         std::fs::create_dir_all(&self.config.log_directory)?;
 
+        // Route syscalls through the middleware chain if one is configured.
+        // Recording and replay sit innermost, right next to the host.
+        let mut layers = self.config.middleware.clone();
+        // The console is outermost, closest to the guest
+        if let Some(console) = console {
+            layers.insert(0, console);
+        }
+        let output_capture = self
+            .config
+            .capture_output
+            .then(|| std::sync::Arc::new(OutputCapture::new(MAX_CAPTURED_OUTPUT)));
+        if let Some(capture) = &output_capture {
+            layers.insert(0, capture.clone());
+        }
+        if let Some(deterministic) = self.config.deterministic {
+            layers.push(std::sync::Arc::new(DeterministicLayer::new(deterministic)));
+        }
+        let artifact_collector = match &self.config.artifacts {
+            Some(artifacts) => {
+                let staging =
+                    create_run_directory(Path::new(&self.config.tmp_directory), "artifacts")?;
+                Some(std::sync::Arc::new(ArtifactCollector::new(
+                    artifacts.clone(),
+                    staging,
+                )?))
+            }
+            None => None,
+        };
+        if let Some(collector) = &artifact_collector {
+            layers.push(collector.clone());
+        }
+        let overlay = if self.config.overlay {
+            let upper = create_run_directory(Path::new(&self.config.tmp_directory), "overlay")?;
+            let passthrough = vec![
+                self.config.tmp_directory.clone().into(),
+                self.config.log_directory.clone().into(),
+            ];
+            Some(std::sync::Arc::new(OverlayLayer::new(upper, passthrough)?))
+        } else {
+            None
+        };
+        if let Some(overlay) = &overlay {
+            layers.push(overlay.clone());
+        }
+        let recorder = self
+            .config
+            .syscall_recording
+            .as_ref()
+            .map(|_| std::sync::Arc::new(Recorder::new()));
+        if let Some(recorder) = &recorder {
+            layers.push(recorder.clone());
+        }
+        let replayer = match &self.config.syscall_replay {
+            Some(path) => Some(std::sync::Arc::new(Replayer::load(path)?)),
+            None => None,
+        };
+        if let Some(replayer) = &replayer {
+            layers.push(replayer.clone());
+        }
+        let intercepts_syscalls = !layers.is_empty();
+        let violation_tracker = intercepts_syscalls.then(|| {
+            let tracker = std::sync::Arc::new(ViolationTracker::default());
+            layers.insert(0, tracker.clone());
+            tracker
+        });
+        let _chain_guard = if layers.is_empty() {
+            None
+        } else {
+            Some(middleware::install(layers).await)
+        };
+
         // Use syscall table provided by embedder, or create default one
         let syscall_table = self.config.syscall_table.clone().or_else(|| {
             use nanvix::sandbox::SyscallTable;
-            Some(std::sync::Arc::new(SyscallTable::new(())))
+            if !intercepts_syscalls {
+                Some(std::sync::Arc::new(SyscallTable::new(())))
+            } else {
+                Some(std::sync::Arc::new(middleware::syscall_table()))
+            }
         });
 
         // Convert workload path to absolute path before potentially changing directory
-        let absolute_workload_path = workload_path
-            .canonicalize()
-            .unwrap_or_else(|_| {
-                std::env::current_dir()
-                    .unwrap_or_default()
-                    .join(workload_path)
-            })
-            .to_string_lossy()
-            .to_string();
-
-        // For Python workloads, change to the registry directory
-        let original_dir = if matches!(workload_type, WorkloadType::Python) {
-            let current_dir = std::env::current_dir().ok();
-            let registry_base = std::path::Path::new(&binary_path)
-                .parent()
-                .and_then(|p| p.parent());
-
-            if let Some(base_path) = registry_base {
-                if let Err(e) = std::env::set_current_dir(base_path) {
-                    log::warn!(
-                        "Failed to change directory to {}: {}",
-                        base_path.display(),
-                        e
-                    );
-                } else {
-                    log::info!("Changed working directory to: {}", base_path.display());
-                }
-            } else {
-                log::warn!(
-                    "Could not determine registry base directory from binary path: {}",
-                    binary_path
-                );
-            }
-            current_dir
-        } else {
-            None
-        };
+        let absolute_workload_path = workload_path.map(|workload_path| {
+            workload_path
+                .canonicalize()
+                .unwrap_or_else(|_| {
+                    std::env::current_dir()
+                        .unwrap_or_default()
+                        .join(workload_path)
+                })
+                .to_string_lossy()
+                .to_string()
+        });
 
         // Configure sandbox cache
         let console_log_path = format!("{}/guest-console.log", &self.config.log_directory);
         let console_file = Some(console_log_path.clone());
 
+        // The toolchain and snapshot live in the tmp directory, so runtimes with
+        // different tmp directories do not share them
+        let tmp_directory = Path::new(&self.config.tmp_directory);
+        let toolchain_path = tmp_directory.join("toolchain");
+        let snapshot_path = tmp_directory.join("snapshot.bin");
         let sandbox_cache_config = SandboxCacheConfig::new(
             nanvix::syscomm::SocketType::Unix,
             nanvix::syscomm::SocketType::Unix,
@@ -252,10 +1050,10 @@ impl Runtime {
             None,
             &kernel_path,
             syscall_table,
-            "/tmp/hyperlight-nanvix/toolchain",
+            &toolchain_path.to_string_lossy(),
             &self.config.log_directory,
             false,
-            "/tmp/hyperlight-nanvix/snapshot.bin",
+            &snapshot_path.to_string_lossy(),
             &self.config.tmp_directory,
         );
 
@@ -263,40 +1061,59 @@ impl Runtime {
         let mut terminal: Terminal<()> = Terminal::new(sandbox_cache_config);
 
         // Prepare execution paths and metadata
-        let (script_args, script_name) =
-            self.prepare_script_args(workload_type, Path::new(&absolute_workload_path))?;
-        let effective_binary_path = match workload_type {
-            WorkloadType::Python => "bin/python3".to_string(),
-            WorkloadType::Binary => absolute_workload_path.clone(),
-            _ => binary_path.clone(),
+        let (script_args, script_name) = match &absolute_workload_path {
+            Some(path) => self.prepare_script_args(workload_type, Path::new(path))?,
+            None => Self::interactive_args(workload_type),
         };
-        let effective_script_args = match workload_type {
-            WorkloadType::Binary => String::new(), // No args for binary execution
-            _ => script_args,
+        let effective_binary_path = match (workload_type, &python_base) {
+            (WorkloadType::Python, Some((interpreter, _))) => {
+                interpreter.to_string_lossy().into_owned()
+            }
+            (WorkloadType::Binary, _) => absolute_workload_path.clone().unwrap_or_default(),
+            _ => binary_path.clone(),
         };
+        let effective_script_args = std::iter::once(script_args)
+            .chain(guest_args.iter().cloned())
+            .filter(|arg| !arg.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
 
-        let unique_app_name = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_nanos()
-            .to_string();
+        let unique_app_name = unique_run_id()?;
 
-        log::info!(
-            "Running {} workload: {:?}",
-            workload_type.binary_name(),
-            workload_path
-        );
+        match workload_path {
+            Some(workload_path) => log::info!(
+                "Running {} workload: {:?}",
+                workload_type.binary_name(),
+                workload_path
+            ),
+            None => log::info!("Starting {} REPL", workload_type.binary_name()),
+        }
         log::debug!("Binary path: {}", effective_binary_path);
         log::debug!("Script args: {}", effective_script_args);
 
+        // For Python workloads, change to the directory holding the sysroot
+        let original_dir = match &python_base {
+            Some((_, base)) => {
+                let current_dir = std::env::current_dir()?;
+                std::env::set_current_dir(base)
+                    .with_context(|| format!("Failed to change directory to {}", base.display()))?;
+                log::info!("Changed working directory to: {}", base.display());
+                Some(current_dir)
+            }
+            None => None,
+        };
+
         // Execute workload
-        terminal
+        let started = std::time::Instant::now();
+        let run_result = terminal
             .run(
                 Some(&script_name),
                 Some(&unique_app_name),
                 &effective_binary_path,
                 &effective_script_args,
             )
-            .await?;
+            .await;
+        let duration = started.elapsed();
 
         // Restore original working directory if we changed it for Python
         if let Some(original_dir) = original_dir {
@@ -305,7 +1122,67 @@ impl Runtime {
             }
         }
 
-        Ok(())
+        if let (Some(recorder), Some(path)) = (&recorder, &self.config.syscall_recording) {
+            recorder.save(path)?;
+            log::info!("Recorded {} syscalls to {}", recorder.records().len(), path);
+        }
+
+        let (stdout, stderr) = match &output_capture {
+            Some(capture) => capture.take(),
+            None => (Vec::new(), Vec::new()),
+        };
+        let mut result = RunResult {
+            artifacts: Vec::new(),
+            overlay: None,
+            stdout,
+            stderr,
+            output_truncated: output_capture
+                .as_ref()
+                .is_some_and(|capture| capture.truncated()),
+            violations: violation_tracker
+                .map(|tracker| tracker.take())
+                .unwrap_or_default(),
+            duration,
+            kernel,
+            interpreter,
+        };
+
+        // A divergence makes later syscalls fail, so it is reported even when
+        // the guest failed
+        let run_result = match &replayer {
+            Some(replayer) => replayer.check(run_result),
+            None => run_result,
+        };
+
+        // The artifact staging and overlay directories are removed when their
+        // layers are dropped, so early returns leave nothing behind
+        if let Err(error) = run_result {
+            return Err(RunFailure { error, result }.into());
+        }
+
+        if let Some(collector) = &artifact_collector {
+            result.artifacts = collector.collect()?;
+        }
+
+        if let Some(overlay) = &overlay {
+            result.overlay = Some(overlay.diff()?);
+        }
+
+        Ok(result)
+    }
+
+    /// Arguments and name of an interpreter started interactively
+    fn interactive_args(workload_type: WorkloadType) -> (String, String) {
+        let script_args = match workload_type {
+            WorkloadType::JavaScript => "-i",
+            // Prompts are shown even though stdin is not a terminal in the guest
+            WorkloadType::Python => "-S -I -i",
+            WorkloadType::Binary => "",
+        };
+        (
+            script_args.to_string(),
+            workload_type.binary_name().to_string(),
+        )
     }
 
     fn prepare_script_args(
@@ -337,3 +1214,32 @@ impl Runtime {
         Ok((script_args, script_name))
     }
 }
+
+/// Identifier unique to a run, used to name per-run resources
+fn unique_run_id() -> Result<String> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_nanos()
+        .to_string())
+}
+
+/// Create a fresh directory `<parent>/<prefix>-<id>` for a run.
+///
+/// Runs of other runtimes or processes may share `parent`, so a name that is
+/// already taken is never reused.
+fn create_run_directory(parent: &Path, prefix: &str) -> Result<std::path::PathBuf> {
+    std::fs::create_dir_all(parent)?;
+    loop {
+        let dir = parent.join(format!("{}-{}", prefix, unique_run_id()?));
+        match std::fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+        }
+    }
+}
+
+/// Directory a Python interpreter's sysroot is installed in, two levels above it
+fn python_base(interpreter: &Path) -> Option<&Path> {
+    interpreter.parent()?.parent()
+}
@@ -0,0 +1,727 @@
+//! Sources the runtime fetches uncached Nanvix binaries from.
+//!
+//! By default binaries come from the upstream Nanvix registry. An
+//! [`ArtifactSource`] selected with `RuntimeConfig::with_artifact_source` can
+//! serve them instead from a local directory, a `file://` or HTTP mirror, or
+//! memory. Every source uses the layout of the registry cache: binaries in
+//! `bin/`, the Python sysroot in `lib/python3.12`, and optionally the SHA-256
+//! digests of the binaries in `manifest.json`. Fetched binaries are checked
+//! against the digests their source publishes.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use nanvix::registry::Registry;
+use tokio::io::AsyncWriteExt;
+
+use crate::cache;
+
+/// Boxed future returned by [`ArtifactSource::fetch`]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Progress of an artifact download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Artifact being downloaded, relative to the registry root (e.g. `bin/qjs`)
+    pub artifact: String,
+    /// Bytes downloaded so far, including bytes resumed from an earlier attempt
+    pub downloaded: u64,
+    /// Size of the artifact, when the source knows it
+    pub total: Option<u64>,
+}
+
+/// Callback receiving the progress of downloads
+pub type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+/// Report progress to an optional callback
+fn report(
+    progress: Option<&ProgressCallback>,
+    artifact: &str,
+    downloaded: u64,
+    total: Option<u64>,
+) {
+    if let Some(progress) = progress {
+        progress(&DownloadProgress {
+            artifact: artifact.to_string(),
+            downloaded,
+            total,
+        });
+    }
+}
+
+/// Report a binary placed in `staging` as complete
+fn report_complete(progress: Option<&ProgressCallback>, staging: &Path, binary_name: &str) {
+    let artifact = format!("bin/{}", binary_name);
+    if let Ok(metadata) = std::fs::metadata(staging.join(&artifact)) {
+        report(progress, &artifact, metadata.len(), Some(metadata.len()));
+    }
+}
+
+/// A place Nanvix binaries are fetched from when they are not cached
+pub trait ArtifactSource: Send + Sync {
+    /// Short description for logs and errors, e.g. the mirror URL
+    fn describe(&self) -> String;
+
+    /// Place `binary_name` at `bin/<binary_name>` below `staging`, along with
+    /// the files it needs (the Python sysroot for `python3`).
+    ///
+    /// `staging` survives a failed fetch, so sources can resume partial downloads.
+    fn fetch<'a>(
+        &'a self,
+        binary_name: &'a str,
+        staging: &'a Path,
+        progress: Option<&'a ProgressCallback>,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// Hex-encoded SHA-256 digest the source publishes for `binary_name`.
+    ///
+    /// Sources that publish no digests return `None`; their binaries are
+    /// trusted on first use.
+    fn digest<'a>(&'a self, _binary_name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// The source serving the pinned Nanvix release `tag`.
+    ///
+    /// Sources that cannot select a release reject pinning.
+    fn release(&self, tag: &str) -> Result<Arc<dyn ArtifactSource>> {
+        anyhow::bail!(
+            "{} cannot serve the Nanvix release {}",
+            self.describe(),
+            tag
+        )
+    }
+
+    /// Binaries the source publishes, sorted by name.
+    ///
+    /// Sources that publish no list of their binaries return an error.
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(async move {
+            anyhow::bail!(
+                "{} does not publish a list of its binaries",
+                self.describe()
+            )
+        })
+    }
+}
+
+/// Start and total size from the `Content-Range` header of `response`, either of
+/// which is `None` when unknown (`bytes */<total>`, `bytes <start>-<end>/*`)
+fn content_range(response: &reqwest::Response) -> Option<(Option<u64>, Option<u64>)> {
+    let value = response
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range
+        .split_once('-')
+        .and_then(|(start, _)| start.parse().ok());
+    Some((start, total.parse().ok()))
+}
+
+/// Files a binary is fetched with, relative to the registry root
+fn artifact_paths(binary_name: &str) -> Vec<String> {
+    let mut paths = vec![format!("bin/{}", binary_name)];
+    if binary_name == "python3" {
+        paths.push(cache::PYTHON_SYSROOT.to_string());
+    }
+    paths
+}
+
+/// The upstream Nanvix registry.
+///
+/// The registry serves only its current release, so pinned releases must come
+/// from another source. It publishes no list of its binaries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RegistrySource;
+
+impl ArtifactSource for RegistrySource {
+    fn describe(&self) -> String {
+        "Nanvix registry".to_string()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        binary_name: &'a str,
+        staging: &'a Path,
+        progress: Option<&'a ProgressCallback>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let registry = Registry::new(Some(staging.to_string_lossy().to_string()));
+            let downloaded = registry
+                .get_cached_binary("hyperlight", "single-process", binary_name)
+                .await?;
+            let target = cache::get_binary_cache_directory(staging).join(binary_name);
+            if Path::new(&downloaded) != target {
+                std::fs::create_dir_all(cache::get_binary_cache_directory(staging))?;
+                std::fs::rename(&downloaded, &target)?;
+            }
+            report_complete(progress, staging, binary_name);
+            Ok(())
+        })
+    }
+}
+
+/// A local directory with the layout of the registry cache
+#[derive(Debug, Clone)]
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ArtifactSource for DirectorySource {
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        binary_name: &'a str,
+        staging: &'a Path,
+        progress: Option<&'a ProgressCallback>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            for path in artifact_paths(binary_name) {
+                let source = self.root.join(&path);
+                if !source.exists() {
+                    anyhow::bail!("{} is missing from {}", path, self.root.display());
+                }
+                copy_tree(&source, &staging.join(&path))?;
+            }
+            report_complete(progress, staging, binary_name);
+            Ok(())
+        })
+    }
+
+    /// Digests are read from the directory's `manifest.json`, if it has one
+    fn digest<'a>(&'a self, binary_name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            if !self.root.join(cache::MANIFEST_FILE).is_file() {
+                return Ok(None);
+            }
+            let manifest = cache::Manifest::load(&self.root)?;
+            Ok(manifest
+                .binaries
+                .get(binary_name)
+                .map(|entry| entry.sha256.clone()))
+        })
+    }
+
+    /// Releases are served from `releases/<tag>`, like the registry cache
+    fn release(&self, tag: &str) -> Result<Arc<dyn ArtifactSource>> {
+        Ok(Arc::new(Self::new(cache::get_release_directory(
+            &self.root, tag,
+        ))))
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(async move { list_directory(&cache::get_binary_cache_directory(&self.root)) })
+    }
+}
+
+/// A mirror of the registry served at a `file://` or `http(s)://` URL.
+///
+/// Binaries are fetched from `<url>/bin/<name>`, and those of a pinned release
+/// from `<url>/releases/<tag>/bin/<name>`. Over HTTP the Python sysroot is
+/// fetched as the archive `<url>/lib/python3.12.tar.zst`.
+#[derive(Debug, Clone)]
+pub struct MirrorSource {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl MirrorSource {
+    pub fn new<S: Into<String>>(url: S) -> Result<Self> {
+        let url = url.into().trim_end_matches('/').to_string();
+        if !["file://", "http://", "https://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme))
+        {
+            anyhow::bail!("Unsupported mirror URL {}", url);
+        }
+        Ok(Self {
+            url,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Download `<url>/<path>` to `dest`, resuming an earlier partial download.
+    ///
+    /// A download is resumed only while the artifact is unchanged: the range
+    /// request carries the `ETag` (or `Last-Modified`) of the first response in
+    /// `If-Range`, so a mirror whose artifact changed sends it whole instead.
+    async fn download(
+        &self,
+        path: &str,
+        dest: &Path,
+        progress: Option<&ProgressCallback>,
+    ) -> Result<()> {
+        let url = format!("{}/{}", self.url, path);
+        let partial = PathBuf::from(format!("{}.part", dest.display()));
+        let validator_path = PathBuf::from(format!("{}.part.validator", dest.display()));
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let validator = tokio::fs::read_to_string(&validator_path).await.ok();
+        let offset = match &validator {
+            Some(_) => tokio::fs::metadata(&partial)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(0),
+            // Without a validator the partial file cannot be trusted
+            None => 0,
+        };
+
+        let mut request = self.client.get(&url);
+        if let (true, Some(validator)) = (offset > 0, &validator) {
+            request = request
+                .header(reqwest::header::RANGE, format!("bytes={}-", offset))
+                .header(reqwest::header::IF_RANGE, validator.as_str());
+        }
+        let mut response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?;
+
+        if offset > 0 && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file already holds the whole artifact
+            if content_range(&response).and_then(|(_, total)| total) == Some(offset) {
+                tokio::fs::rename(&partial, dest).await?;
+                let _ = tokio::fs::remove_file(&validator_path).await;
+                return Ok(());
+            }
+            // Otherwise it is stale: start over
+            response = self
+                .client
+                .get(&url)
+                .send()
+                .await
+                .with_context(|| format!("Failed to download {}", url))?;
+        }
+        response = response
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", url))?;
+
+        let resumed = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        let total = if resumed {
+            match content_range(&response) {
+                Some((Some(start), total)) if start == offset => total,
+                _ => anyhow::bail!("{} answered with an unexpected range", url),
+            }
+        } else {
+            response.content_length()
+        };
+        let validator = [reqwest::header::ETAG, reqwest::header::LAST_MODIFIED]
+            .iter()
+            .find_map(|name| response.headers().get(name)?.to_str().ok())
+            .map(str::to_string);
+        match &validator {
+            Some(validator) => tokio::fs::write(&validator_path, validator).await?,
+            None => {
+                let _ = tokio::fs::remove_file(&validator_path).await;
+            }
+        }
+
+        let mut downloaded = if resumed { offset } else { 0 };
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&partial)
+            .await?;
+        report(progress, path, downloaded, total);
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("Download of {} was interrupted", url))?
+        {
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+            report(progress, path, downloaded, total);
+        }
+        file.sync_all().await?;
+        if let Some(total) = total {
+            if downloaded != total {
+                anyhow::bail!(
+                    "Download of {} ended after {} of {} bytes",
+                    url,
+                    downloaded,
+                    total
+                );
+            }
+        }
+        tokio::fs::rename(&partial, dest).await?;
+        let _ = tokio::fs::remove_file(&validator_path).await;
+        Ok(())
+    }
+
+    /// Download the mirror's `manifest.json`, or `None` if it publishes none
+    async fn manifest(&self) -> Result<Option<cache::Manifest>> {
+        let url = format!("{}/{}", self.url, cache::MANIFEST_FILE);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let manifest = response
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", url))?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse {}", url))?;
+        Ok(Some(manifest))
+    }
+}
+
+impl ArtifactSource for MirrorSource {
+    fn describe(&self) -> String {
+        self.url.clone()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        binary_name: &'a str,
+        staging: &'a Path,
+        progress: Option<&'a ProgressCallback>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if let Some(root) = self.url.strip_prefix("file://") {
+                return DirectorySource::new(root)
+                    .fetch(binary_name, staging, progress)
+                    .await;
+            }
+            for path in artifact_paths(binary_name) {
+                if path == cache::PYTHON_SYSROOT {
+                    let archive = staging.join(format!("{}.tar.zst", path));
+                    self.download(&format!("{}.tar.zst", path), &archive, progress)
+                        .await?;
+                    let sysroot = staging.join(&path);
+                    tokio::task::spawn_blocking(move || -> Result<()> {
+                        let file = std::fs::File::open(&archive)?;
+                        tar::Archive::new(zstd::Decoder::new(file)?).unpack(sysroot)?;
+                        std::fs::remove_file(&archive)?;
+                        Ok(())
+                    })
+                    .await??;
+                } else {
+                    self.download(&path, &staging.join(&path), progress).await?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Digests are read from the mirror's `manifest.json`, if it has one
+    fn digest<'a>(&'a self, binary_name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            if let Some(root) = self.url.strip_prefix("file://") {
+                return DirectorySource::new(root).digest(binary_name).await;
+            }
+            Ok(self
+                .manifest()
+                .await?
+                .and_then(|manifest| manifest.binaries.get(binary_name).cloned())
+                .map(|entry| entry.sha256))
+        })
+    }
+
+    /// Releases are served from `<url>/releases/<tag>`
+    fn release(&self, tag: &str) -> Result<Arc<dyn ArtifactSource>> {
+        Ok(Arc::new(Self {
+            url: format!("{}/releases/{}", self.url, tag),
+            client: self.client.clone(),
+        }))
+    }
+
+    /// Over HTTP, the binaries are read from the mirror's `manifest.json`
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+        Box::pin(async move {
+            if let Some(root) = self.url.strip_prefix("file://") {
+                return DirectorySource::new(root).list().await;
+            }
+            let manifest = self
+                .manifest()
+                .await?
+                .with_context(|| format!("{} publishes no {}", self.url, cache::MANIFEST_FILE))?;
+            Ok(manifest.binaries.into_keys().collect())
+        })
+    }
+}
+
+/// Binaries held in memory, keyed by their path relative to the registry root
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file, e.g. `with_file("bin/kernel.elf", bytes)`
+    pub fn with_file<S: Into<String>>(mut self, path: S, contents: Vec<u8>) -> Self {
+        self.files.insert(path.into(), contents);
+        self
+    }
+}
+
+impl ArtifactSource for MemorySource {
+    fn describe(&self) -> String {
+        "in-memory source".to_string()
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        binary_name: &'a str,
+        staging: &'a Path,
+        progress: Option<&'a ProgressCallback>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            for prefix in artifact_paths(binary_name) {
+                let mut found = false;
+                for (path, contents) in &self.files {
+                    let below = path
+                        .strip_prefix(&prefix)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+                    if below {
+                        let dest = staging.join(path);
+                        if let Some(parent) = dest.parent() {
+                            std::fs::create_dir_all(parent)?;
+                        }
+                        std::fs::write(dest, contents)?;
+                        found = true;
+                    }
+                }
+                if !found {
+                    anyhow::bail!("{} is not in the in-memory source", prefix);
+                }
+            }
+            report_complete(progress, staging, binary_name);
+            Ok(())
+        })
+    }
+
+    /// The digest of the binary held in memory
+    fn digest<'a>(&'a self, binary_name: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        let digest = self
+            .files
+            .get(&format!("bin/{}", binary_name))
+            .map(|contents| cache::sha256_hex(contents));
+        Box::pin(async move { Ok(digest) })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<String>>> {
+        let mut names: Vec<String> = self
+            .files
+            .keys()
+            .filter_map(|path| path.strip_prefix("bin/"))
+            .map(|name| name.to_string())
+            .collect();
+        names.sort();
+        Box::pin(async move { Ok(names) })
+    }
+}
+
+/// Names of the regular files in `dir`, sorted
+fn list_directory(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to list {:?}", dir))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Copy a file or directory tree
+fn copy_tree(source: &Path, dest: &Path) -> Result<()> {
+    if source.is_dir() {
+        std::fs::create_dir_all(dest)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(source, dest)
+            .with_context(|| format!("Failed to copy {}", source.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_fetch_from_http_mirror() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Local stand-in for an internal mirror, serving qjs only
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]);
+                let response = if request.starts_with("GET /bin/qjs ") {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\nqjs"
+                } else if request.starts_with("GET /releases/v1.0/bin/qjs ") {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nqjs1"
+                } else {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let root = TempDir::new("mirror-source");
+        let source = MirrorSource::new(format!("{}/", url)).unwrap();
+        let path = cache::fetch_binary(&root, &source, "qjs", None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"qjs");
+        assert!(cache::fetch_binary(&root, &source, "kernel.elf", None)
+            .await
+            .is_err());
+        assert!(MirrorSource::new("ftp://mirror").is_err());
+
+        // Pinned releases are served from their own directory of the mirror
+        let release = TempDir::new("mirror-release");
+        let source = source.release("v1.0").unwrap();
+        let path = cache::fetch_binary(&release, source.as_ref(), "qjs", None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"qjs1");
+        assert!(RegistrySource.release("v1.0").is_err());
+        assert!(RegistrySource.list().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mirror_download_resumes() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Mirror that drops the first connection after four bytes
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            let body = b"0123456789";
+            let manifest = format!(
+                r#"{{"binaries":{{"qjs":{{"sha256":"{}","size":10,"mtime":0}}}}}}"#,
+                cache::sha256_hex(body)
+            );
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0u8; 1024];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_lowercase();
+                if request.starts_with("get /manifest.json ") {
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        manifest.len(),
+                        manifest
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    continue;
+                }
+                // Resume only requests that prove the artifact is unchanged
+                let range = request.contains("\r\nrange: bytes=4-")
+                    && request.contains("\r\nif-range: \"v1\"");
+                seen.lock().unwrap().push(range);
+                let response = if range {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: 6\r\nContent-Range: bytes 4-9/10\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n{}",
+                        String::from_utf8_lossy(&body[4..])
+                    )
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n{}",
+                        String::from_utf8_lossy(&body[..4])
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let root = TempDir::new("resume");
+        let source = MirrorSource::new(url).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let progress: ProgressCallback = Arc::new(move |progress: &DownloadProgress| {
+            recorded.lock().unwrap().push(progress.clone());
+        });
+
+        assert!(cache::fetch_binary(&root, &source, "qjs", Some(&progress))
+            .await
+            .is_err());
+        let path = cache::fetch_binary(&root, &source, "qjs", Some(&progress))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"0123456789");
+        assert_eq!(*requests.lock().unwrap(), vec![false, true]);
+
+        let events = events.lock().unwrap();
+        let last = events.last().unwrap();
+        assert_eq!(last.artifact, "bin/qjs");
+        assert_eq!((last.downloaded, last.total), (10, Some(10)));
+        assert!(!root.join(".download-qjs").exists());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_checks_published_digest() {
+        let root = TempDir::new("published-digest");
+        let mirror = root.join("mirror");
+        std::fs::create_dir_all(mirror.join("bin")).unwrap();
+        std::fs::write(mirror.join("bin/qjs"), b"tampered").unwrap();
+        let mut manifest = cache::Manifest::default();
+        manifest.binaries.insert(
+            "qjs".to_string(),
+            cache::ManifestEntry {
+                sha256: cache::sha256_hex(b"qjs"),
+                size: 3,
+                mtime: 0,
+            },
+        );
+        manifest.save(&mirror).unwrap();
+
+        let cache_dir = root.join("cache");
+        let source = DirectorySource::new(&mirror);
+        let error = cache::fetch_binary(&cache_dir, &source, "qjs", None)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("integrity"));
+        assert!(!cache::is_binary_cached(&cache_dir, "qjs"));
+
+        std::fs::write(mirror.join("bin/qjs"), b"qjs").unwrap();
+        let path = cache::fetch_binary(&cache_dir, &source, "qjs", None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"qjs");
+    }
+}
@@ -0,0 +1,115 @@
+//! Helpers shared by the unit tests of the crate's modules.
+
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::middleware::{Next, Syscall, SyscallMiddleware, SyscallResult};
+
+/// A fresh directory below the system temp directory, removed when dropped
+/// even if the test panics
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create a directory whose name starts with `name` and is unique to this
+    /// test process and call
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "hyperlight-nanvix-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `path` relative to the working directory of the test process, which stays
+/// unchanged since the tests share it
+pub(crate) fn relative_to_cwd(path: &Path) -> String {
+    let cwd = std::env::current_dir().unwrap();
+    format!(
+        "{}{}",
+        "../".repeat(cwd.components().count() - 1),
+        path.strip_prefix("/").unwrap().display()
+    )
+}
+
+/// Middleware that records the layer name and optionally short-circuits
+pub(crate) struct TracingLayer {
+    pub name: &'static str,
+    pub trace: Arc<Mutex<Vec<&'static str>>>,
+    pub errno: Option<i32>,
+}
+
+impl SyscallMiddleware for TracingLayer {
+    fn handle(&self, call: &mut Syscall<'_>, next: Next<'_>) -> SyscallResult {
+        self.trace.lock().unwrap().push(self.name);
+        match self.errno {
+            Some(errno) => Err(errno),
+            None => next.run(call),
+        }
+    }
+}
+
+/// Open, read and close `path` through a middleware chain
+pub(crate) fn read_through_chain(layers: &[Arc<dyn SyscallMiddleware>], path: &str) -> Vec<u8> {
+    let mut open = Syscall::Openat {
+        dirfd: libc::AT_FDCWD,
+        path: CString::new(path).unwrap(),
+        flags: libc::O_RDONLY,
+        mode: 0,
+    };
+    let fd = Next::new(layers).run(&mut open).expect("openat failed") as i32;
+
+    let mut buf = [0u8; 64];
+    let len = Next::new(layers)
+        .run(&mut Syscall::Read { fd, buf: &mut buf })
+        .expect("read failed") as usize;
+
+    Next::new(layers)
+        .run(&mut Syscall::Close { fd })
+        .expect("close failed");
+    buf[..len].to_vec()
+}
+
+/// Create `guest_path` and write `contents` to it through a middleware chain
+pub(crate) fn write_through_chain(
+    layers: &[Arc<dyn SyscallMiddleware>],
+    guest_path: &str,
+    contents: &[u8],
+) -> SyscallResult {
+    let mut open = Syscall::Openat {
+        dirfd: libc::AT_FDCWD,
+        path: CString::new(guest_path).unwrap(),
+        flags: libc::O_CREAT | libc::O_WRONLY | libc::O_TRUNC,
+        mode: 0o644,
+    };
+    let fd = Next::new(layers).run(&mut open)? as i32;
+    let written = Next::new(layers).run(&mut Syscall::Write { fd, buf: contents });
+    Next::new(layers).run(&mut Syscall::Close { fd })?;
+    written
+}
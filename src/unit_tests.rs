@@ -1,8 +1,9 @@
 #[cfg(test)]
 mod tests {
     use crate::runtime::{Runtime, WorkloadType};
+    use crate::test_util::{TempDir, TracingLayer};
     use crate::*;
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn test_workload_type_detection() {
@@ -38,6 +39,162 @@ mod tests {
         assert!(config.syscall_table.is_none());
     }
 
+    #[test]
+    fn test_runtime_config_cache_directory() {
+        let config = RuntimeConfig::new();
+        assert_eq!(
+            config.resolve_cache_directory(),
+            cache::get_cache_directory()
+        );
+
+        let config = config.with_cache_directory("/custom/cache");
+        assert_eq!(
+            config.resolve_cache_directory(),
+            std::path::PathBuf::from("/custom/cache")
+        );
+        let runtime = Runtime::new(config).unwrap();
+        assert_eq!(
+            runtime.cache_directory(),
+            std::path::Path::new("/custom/cache")
+        );
+    }
+
+    #[test]
+    fn test_runtime_config_nanvix_release() {
+        let config = RuntimeConfig::new().with_cache_directory("/custom/cache");
+        assert_eq!(
+            config.resolve_release_directory(),
+            std::path::PathBuf::from("/custom/cache")
+        );
+
+        // The upstream registry cannot serve a pinned release
+        assert!(Runtime::new(config.clone().with_nanvix_release("v1.0")).is_err());
+
+        // Runtimes pinned to different releases use separate directories
+        let config = config.with_artifact_source(Arc::new(DirectorySource::new("/mirror")));
+        let v1 = Runtime::new(config.clone().with_nanvix_release("v1.0")).unwrap();
+        let v2 = Runtime::new(config.clone().with_nanvix_release("v2.0")).unwrap();
+        assert_eq!(
+            v1.release_directory(),
+            std::path::Path::new("/custom/cache/releases/v1.0")
+        );
+        assert_eq!(
+            v2.release_directory(),
+            std::path::Path::new("/custom/cache/releases/v2.0")
+        );
+        assert_eq!(v1.cache_directory(), v2.cache_directory());
+
+        assert!(Runtime::new(config.with_nanvix_release("../v1.0")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_runtime_prefetch() {
+        let root = TempDir::new("prefetch");
+        let source = MemorySource::new()
+            .with_file("bin/kernel.elf", b"kernel".to_vec())
+            .with_file("bin/qjs", b"qjs".to_vec())
+            .with_file("bin/python3", b"python".to_vec())
+            .with_file("lib/python3.12/os.py", b"import sys".to_vec());
+        let config = RuntimeConfig::new()
+            .with_cache_directory(root.to_string_lossy())
+            .with_artifact_source(Arc::new(source));
+        let runtime = Runtime::new(config).unwrap();
+
+        assert_eq!(
+            runtime.available_binaries().await.unwrap(),
+            vec!["kernel.elf", "python3", "qjs"]
+        );
+
+        // Only what the listed workloads need is fetched
+        runtime.prefetch(&[WorkloadType::JavaScript]).await.unwrap();
+        assert!(cache::is_binary_cached(&root, "kernel.elf"));
+        assert!(cache::is_binary_cached(&root, "qjs"));
+        assert!(!cache::is_binary_cached(&root, "python3"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_kernel_and_interpreter() {
+        let root = TempDir::new("custom-binary");
+        let build = root.join("build");
+        std::fs::create_dir_all(build.join("bin")).unwrap();
+        std::fs::create_dir_all(build.join("lib/python3.12")).unwrap();
+        std::fs::write(build.join("kernel.elf"), b"kernel").unwrap();
+        std::fs::write(build.join("bin/python3"), b"python").unwrap();
+        let offline = root.join("offline");
+        std::fs::create_dir_all(&offline).unwrap();
+
+        // Overridden binaries need not be present in the offline directory
+        let config = RuntimeConfig::new()
+            .with_offline_directory(offline.to_string_lossy())
+            .with_kernel_path(build.join("kernel.elf").to_string_lossy())
+            .with_interpreter_path(
+                WorkloadType::Python,
+                build.join("bin/python3").to_string_lossy(),
+            );
+        let runtime = Runtime::new(config.clone()).unwrap();
+        runtime.prefetch(&[WorkloadType::Python]).await.unwrap();
+        assert!(runtime.prefetch(&[WorkloadType::JavaScript]).await.is_err());
+
+        // A custom Python interpreter does not fall back to another sysroot
+        std::fs::remove_dir_all(build.join("lib")).unwrap();
+        let runtime = Runtime::new(
+            config
+                .clone()
+                .with_tmp_directory(root.join("tmp").to_string_lossy()),
+        )
+        .unwrap();
+        let error = runtime
+            .run_code(WorkloadType::Python, "print(1)", &[])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("sysroot"));
+
+        let missing_kernel = config.with_kernel_path(root.join("missing.elf").to_string_lossy());
+        let runtime = Runtime::new(missing_kernel).unwrap();
+        assert!(runtime.prefetch(&[WorkloadType::Binary]).await.is_err());
+    }
+
+    #[test]
+    fn test_diagnose_reports_problems_with_fixes() {
+        let root = TempDir::new("diagnose");
+        let offline = root.join("offline");
+        std::fs::create_dir_all(offline.join("bin")).unwrap();
+        std::fs::write(offline.join("bin/kernel.elf"), b"kernel").unwrap();
+        // A file where a directory is expected cannot be written to
+        std::fs::write(root.join("not-a-dir"), b"").unwrap();
+
+        let config = RuntimeConfig::new()
+            .with_offline_directory(offline.to_string_lossy())
+            .with_tmp_directory(root.join("tmp").to_string_lossy())
+            .with_log_directory(root.join("not-a-dir/log").to_string_lossy());
+        let diagnosis = Runtime::new(config).unwrap().diagnose();
+        let check = |name: &str| {
+            diagnosis
+                .checks
+                .iter()
+                .find(|check| check.name == name)
+                .unwrap()
+        };
+
+        assert!(!diagnosis.is_healthy());
+        assert!(!diagnosis.nanvix_revision.is_empty());
+        assert_eq!(check("tmp directory").status, CheckStatus::Pass);
+        // Diagnosing creates nothing
+        assert!(!root.join("tmp").exists());
+        assert_eq!(check("log directory").status, CheckStatus::Fail);
+        assert!(check("log directory").fix.is_some());
+        assert_eq!(check("kernel.elf").status, CheckStatus::Pass);
+        assert_eq!(check("qjs").status, CheckStatus::Fail);
+        assert!(check("qjs").fix.as_deref().unwrap().contains("qjs"));
+        assert_eq!(check("python sysroot").status, CheckStatus::Fail);
+        assert_eq!(diagnosis.artifacts.len(), 1);
+        assert_eq!(diagnosis.artifacts[0].name, "kernel.elf");
+
+        let missing = crate::diagnose::check_kvm(&root.join("kvm"));
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].status, CheckStatus::Fail);
+    }
+
     #[test]
     fn test_runtime_config_with_syscall_table() {
         let syscall_table = Arc::new(SyscallTable::new(()));
@@ -63,4 +220,54 @@ mod tests {
         let runtime = Runtime::new(config);
         assert!(runtime.is_ok());
     }
+
+    #[test]
+    fn test_syscall_table_conflicts_with_middleware() {
+        let config = RuntimeConfig::new()
+            .with_syscall_table(Arc::new(SyscallTable::new(())))
+            .with_syscall_middleware(Arc::new(TracingLayer {
+                name: "tracer",
+                trace: Arc::new(Mutex::new(Vec::new())),
+                errno: None,
+            }));
+
+        assert!(Runtime::new(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_guest_args_reject_whitespace() {
+        let runtime = Runtime::new(RuntimeConfig::new()).unwrap();
+
+        // Rejected before any binary is resolved
+        let result = runtime
+            .run_with_args("guest-examples/hello.js", &["two words".to_string()])
+            .await;
+        assert!(result.unwrap_err().to_string().contains("whitespace"));
+        let result = runtime
+            .run_with_args("guest-examples/hello.js", &[String::new()])
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_code_stages_and_cleans_up() {
+        let root = TempDir::new("run-code");
+        let config = RuntimeConfig::new()
+            .with_tmp_directory(root.join("tmp").to_string_lossy())
+            .with_offline_directory(root.join("offline").to_string_lossy());
+        let runtime = Runtime::new(config).unwrap();
+
+        assert!(runtime
+            .run_code(WorkloadType::Binary, "", &[])
+            .await
+            .is_err());
+
+        // The offline check fails after staging; nothing is left behind
+        let error = runtime
+            .run_code(WorkloadType::Python, "print(2**10)", &[])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("bin/python3"));
+        assert_eq!(std::fs::read_dir(root.join("tmp")).unwrap().count(), 0);
+    }
 }
@@ -109,3 +109,96 @@ async fn test_runtime_config_customization() {
         .await
         .expect("Failed with custom config");
 }
+
+#[test]
+fn test_cli_deprecated_registry_flags() {
+    let cache_dir = std::env::temp_dir().join(format!("deprecated-flags-{}", std::process::id()));
+
+    // `--clear-registry` still works, as an alias of `registry clear`
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_hyperlight-nanvix"))
+        .arg("--clear-registry")
+        .arg("--cache-dir")
+        .arg(&cache_dir)
+        .output()
+        .expect("Failed to run --clear-registry");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("deprecated"));
+
+    // The aliases are hidden from the help
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_hyperlight-nanvix"))
+        .arg("--help")
+        .output()
+        .expect("Failed to run --help");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("--setup-registry"));
+    let _ = std::fs::remove_dir_all(&cache_dir);
+}
+
+#[test]
+fn test_cli_batch_report() {
+    let report = std::env::temp_dir().join(format!("batch-report-{}.json", std::process::id()));
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_hyperlight-nanvix"))
+        .args(["batch", "guest-examples/hello.*", "--jobs", "2", "--json"])
+        .arg(&report)
+        .status()
+        .expect("Failed to run batch");
+    assert!(status.success());
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report).unwrap()).unwrap();
+    assert_eq!(report["passed"], 2);
+    assert_eq!(report["results"][0]["file"], "guest-examples/hello.js");
+}
+
+#[test]
+fn test_cli_serve_run() {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_hyperlight-nanvix"))
+        .args(["serve", "--listen", "127.0.0.1:0", "--max-concurrency", "1"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+    let mut banner = String::new();
+    BufReader::new(server.stdout.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let address = banner
+        .split("http://")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .expect("Server did not print its address")
+        .to_string();
+
+    let post = |body: &str| {
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        write!(
+            stream,
+            "POST /v1/run HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            address,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().to_string();
+        (
+            status,
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+        )
+    };
+
+    let (status, report) = post(r#"{"language": "js", "code": "console.log(1 + 1)"}"#);
+    let (bad_status, error) = post(r#"{"language": "ruby", "code": "puts 1"}"#);
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert_eq!(status, "200");
+    assert_eq!(report["status"], "success");
+    assert_eq!(report["workload_type"], "javascript");
+    assert_eq!(bad_status, "400");
+    assert!(error["error"].as_str().unwrap().contains("ruby"));
+}